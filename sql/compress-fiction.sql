@@ -2,13 +2,15 @@ PRAGMA synchronous = OFF;
 PRAGMA journal_mode = MEMORY;
 
 CREATE TABLE fiction_mini AS 
-select fh.ipfs_cid, f.Title, f.Author, f.Series, f.Language, f.Year, f.Publisher, f.Extension, f.Filesize, f.Locator
+select fh.ipfs_cid, f.Title, f.Author, f.Series, f.Language, f.Year, f.Publisher, f.Extension, f.Filesize, f.Locator, lower(f.MD5) AS MD5
 from fiction f
 join fiction_hashes fh on fh.md5 = lower(f.md5)
 where fh.ipfs_cid is not null and trim(fh.ipfs_cid) <> ''
 ;
 CREATE INDEX "idx_fiction_mini_Search" ON "fiction_mini" (`Author`, `Title`, `Series`, `Language`, `Extension`)
 ;
+CREATE INDEX "idx_fiction_mini_MD5" ON "fiction_mini" (`MD5`)
+;
 drop table fiction ;
 drop table fiction_description ;
 drop table fiction_hashes ;
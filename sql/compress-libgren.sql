@@ -4,13 +4,15 @@ PRAGMA journal_mode = MEMORY;
 DROP TABLE IF EXISTS `non_fiction_mini`;
 
 CREATE TABLE non_fiction_mini AS 
-select fh.ipfs_cid, f.Title, f.Author, f.Series, f.Language, f.Year, f.Publisher, f.Extension, f.Filesize, f.Locator, f.Topic
+select fh.ipfs_cid, f.Title, f.Author, f.Series, f.Language, f.Year, f.Publisher, f.Extension, f.Filesize, f.Locator, f.Topic, lower(f.MD5) AS MD5
 from updated f
 join hashes fh on fh.md5 = lower(f.md5)
 where fh.ipfs_cid is not null and trim(fh.ipfs_cid) <> ''
 ;
 CREATE INDEX "idx_non_fiction_mini_Search" ON "non_fiction_mini" (`Author`, `Title`, `Series`, `Language`, `Extension`)
 ;
+CREATE INDEX "idx_non_fiction_mini_MD5" ON "non_fiction_mini" (`MD5`)
+;
 
 drop table description ;
 drop table description_edited ;
@@ -3,6 +3,7 @@ use std::{
     ops::RangeInclusive,
     path::PathBuf,
     sync::{atomic::Ordering::Relaxed, RwLock},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 use config::Config;
@@ -10,12 +11,19 @@ use egui_extras::{Column, TableBuilder};
 use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
 
 use crate::{
+    authors::{self, AuthorsView},
     config::load_settings,
     db::{
         self, BookRef,
-        Collection::{Fiction, NonFiction},
+        Collection::{All, Fiction, NonFiction},
+        SearchMode,
     },
     download,
+    facets::{self, Facets},
+    search::{self, Field},
+    series::{self, SeriesView},
+    stats,
+    topics::{self, TopicTree},
     uifilter::{filter_update_booklist, UIFilter},
 };
 
@@ -23,6 +31,13 @@ use crate::{
 #[serde(default)]
 pub struct TemplateApp {
     filters: db::Params,
+    // the search as typed, kept in step with `filters`
+    omnibox: String,
+    #[serde(skip)]
+    omnibox_error: Option<search::ParseError>,
+    // why each regex box's pattern doesn't compile, worked out when it changes
+    #[serde(skip)]
+    pattern_errors: Vec<(Field, String)>,
     #[serde(skip)]
     value: f32,
     #[serde(skip)]
@@ -36,6 +51,28 @@ pub struct TemplateApp {
     #[serde(skip)]
     uifilter: UIFilter,
     #[serde(skip)]
+    facets: Facets,
+    // where the next page of results starts, if there is one
+    #[serde(skip)]
+    more: Option<db::Cursor>,
+    // other spellings to try when a search finds nothing
+    #[serde(skip)]
+    suggestions: Vec<db::Params>,
+    // None until asked for
+    #[serde(skip)]
+    topics: Option<Result<TopicTree, String>>,
+    #[serde(skip)]
+    view: View,
+    #[serde(skip)]
+    authors: AuthorsView,
+    #[serde(skip)]
+    series_view: SeriesView,
+    #[serde(skip)]
+    show_stats: bool,
+    // None until the window is first opened
+    #[serde(skip)]
+    stats: Option<Result<db::Stats, String>>,
+    #[serde(skip)]
     config: Config,
 }
 
@@ -44,17 +81,38 @@ impl Default for TemplateApp {
         Self {
             value: 2.7,
             filters: db::Params::default(),
+            omnibox: String::new(),
+            omnibox_error: None,
+            pattern_errors: vec![],
             db: None,
             results: Err(String::from("No results")),
             download: download::Download::new(),
             download_status: download::Status::default(),
             uifilter: UIFilter::default(),
+            facets: Facets::default(),
+            more: None,
+            suggestions: vec![],
+            topics: None,
+            view: View::default(),
+            authors: AuthorsView::default(),
+            series_view: SeriesView::default(),
+            show_stats: false,
+            stats: None,
             config: load_settings(),
         }
     }
 }
 
-const COLUMNS: &'static [&'static str] = &[
+// what the central panel shows
+#[derive(Default, PartialEq)]
+enum View {
+    #[default]
+    Books,
+    Authors,
+    Series,
+}
+
+const COLUMNS: &[&str] = &[
     "Download",
     "Title",
     "Authors",
@@ -65,15 +123,22 @@ const COLUMNS: &'static [&'static str] = &[
     "Duplicates",
     "FileSize",
     "Format",
+    "Collection",
 ];
 
 impl TemplateApp {
     pub fn new(cc: &eframe::CreationContext<'_>) -> Self {
         log::info!("App started");
-        if let Some(storage) = cc.storage {
-            return eframe::get_value(storage, eframe::APP_KEY).unwrap_or_default();
+        let mut app: Self = match cc.storage {
+            Some(storage) => eframe::get_value(storage, eframe::APP_KEY).unwrap_or_default(),
+            None => Default::default(),
+        };
+        // saved before there was an omnibox
+        if app.omnibox.is_empty() {
+            app.omnibox = search::to_query(&app.filters);
         }
-        Default::default()
+        app.pattern_errors = db::invalid_patterns(&app.filters);
+        app
     }
 }
 
@@ -86,90 +151,257 @@ impl eframe::App for TemplateApp {
         let Self {
             value: _,
             filters,
+            omnibox,
+            omnibox_error,
+            pattern_errors,
             db,
             results,
             uifilter,
+            facets,
+            more,
+            suggestions,
+            topics,
+            view,
+            authors,
+            series_view,
+            show_stats,
+            stats,
             download,
             download_status,
             config,
         } = self;
 
         if let Some(db) = db {
-            for _ in 0..10000 {
+            // read batches for a few ms a frame; the rest wait in the channel
+            let started = Instant::now();
+            while started.elapsed() < Duration::from_millis(10) {
                 match db.get_result() {
-                    Some(Ok(newbooks)) => {
-                        read_results(results, newbooks, uifilter, filters.deduplicate, ctx)
+                    Some(db::Response::Books(newbooks)) => {
+                        read_results(
+                            results,
+                            newbooks,
+                            uifilter,
+                            facets,
+                            filters.deduplicate,
+                            ctx,
+                        );
+                        series_view.clear();
+                    }
+                    Some(db::Response::PageEnd(cursor)) => {
+                        // an empty search never sends any books
+                        if results.is_err() {
+                            *results = Ok(vec![]);
+                            suggestions.clear();
+                            series_view.clear();
+                        }
+                        *more = cursor;
                     }
-                    Some(Err(e)) => *results = Err(e.to_string()),
+                    Some(db::Response::Error(e)) => *results = Err(e),
+                    Some(db::Response::Suggestions(s)) => *suggestions = s,
                     None => break,
                 }
             }
+            // side queries answer on their own channels, whatever the search is doing
+            if let Some(t) = db.get_topics() {
+                *topics = Some(t.map(TopicTree::new));
+            }
+            if let Some(s) = db.get_stats() {
+                *stats = Some(s);
+            }
+            if let Some(a) = db.get_authors() {
+                authors.set_authors(a);
+            }
+            if let Some(b) = db.get_bibliography() {
+                authors.set_books(b);
+            }
         } else {
-            *db = Some(db::DB::new());
+            *db = Some(db::DB::new(ctx.clone()));
         }
 
         // For inspiration and more examples, go to https://emilk.github.io/egui
         egui::SidePanel::left("side_panel").show(ctx, |ui| {
             ui.heading("Side Panel");
 
-            let mut changed = true;
-            let f = filters.collection == Fiction;
-            if ui.selectable_label(f, "Fiction").clicked() {
-                filters.collection = Fiction
-            } else if ui.selectable_label(!f, "Nonfiction").clicked() {
-                filters.collection = NonFiction
-            } else {
-                changed = false;
+            let mut changed = false;
+            if let Some(db) = db {
+                for warning in &db.catalog.warnings {
+                    ui.colored_label(ui.visuals().warn_fg_color, warning);
+                }
+                if db.catalog.sources.len() > 1 {
+                    ui.collapsing("Catalog files", |ui| {
+                        for source in &db.catalog.sources {
+                            let collections: Vec<&str> = [Fiction, NonFiction]
+                                .iter()
+                                .filter(|c| source.schema(c).is_some())
+                                .map(|c| c.name())
+                                .collect();
+                            ui.label(format!("{}: {}", source.path, collections.join(", ")));
+                        }
+                    });
+                }
+            }
+            // only offer the collections the catalog file actually has
+            let mut available = match db {
+                Some(db) => db.catalog.collections(),
+                None => vec![],
+            };
+            if available.len() > 1 {
+                available.push(All);
+            }
+            if !available.is_empty() && !available.contains(&filters.collection) {
+                filters.collection = available[0].clone();
+                changed = true;
             }
+            ui.horizontal(|ui| {
+                for collection in [Fiction, NonFiction, All] {
+                    if available.contains(&collection)
+                        && ui
+                            .selectable_label(filters.collection == collection, collection.name())
+                            .clicked()
+                    {
+                        filters.collection = collection;
+                        changed = true;
+                    }
+                }
+            });
 
             changed |= ui
                 .checkbox(&mut filters.deduplicate, "Remove duplicates")
                 .changed();
+            changed |= ui
+                .checkbox(&mut filters.transliterate, "Match Cyrillic and Greek")
+                .on_hover_text("Find Достоевский by typing dostoevsk")
+                .changed();
 
-            changed |= render_filter(ui, "Title", &mut filters.title);
-            changed |= render_filter(ui, "Authors", &mut filters.authors);
-            changed |= render_filter(ui, "Series", &mut filters.series);
-            changed |= render_filter(ui, "Language", &mut filters.language);
-            changed |= render_filter(ui, "Format", &mut filters.format);
+            ui.horizontal(|ui| {
+                ui.label("Match");
+                for (mode, label) in [
+                    (SearchMode::Like, "Anywhere"),
+                    (SearchMode::FullText, "Words"),
+                    (SearchMode::Relevance, "Relevance"),
+                    (SearchMode::Fuzzy, "Fuzzy"),
+                ] {
+                    changed |= ui
+                        .selectable_value(&mut filters.mode, mode, label)
+                        .changed();
+                }
+            });
 
-            if changed {
-                if let Some(db) = db {
-                    db.interrupt();
-                    while db.get_result().is_some() {}
-                    db.query(filters.clone());
-                    *results = Err(String::from("Searching..."));
-                    *uifilter = UIFilter::default();
+            changed |= render_omnibox(ui, omnibox, omnibox_error, pattern_errors, filters);
+
+            if let Some(db) = db {
+                if filters.collection == NonFiction && db.catalog.topics() {
+                    changed |= render_topics(ui, db, topics, &mut filters.topics);
                 }
             }
 
+            if changed {
+                search_again(db, filters, config, results, uifilter, facets, more);
+            }
+            let mut refine = false;
+
             if let Some(db) = db {
                 if db.processing.load(Relaxed) {
-                    ui.spinner();
+                    ui.horizontal(|ui| {
+                        ui.spinner();
+                        ui.label(format!("{} rows read", db.produced.load(Relaxed)));
+                    });
                 }
                 ui.label(format!(
-                    "{} results",
+                    "{}{} results",
                     match results {
                         Ok(v) => v.len(),
                         _ => 0,
-                    }
+                    },
+                    if more.is_some() { "+" } else { "" }
                 ));
             }
 
+            if !facets.is_empty() {
+                egui::CollapsingHeader::new("Refine")
+                    .default_open(true)
+                    .show(ui, |ui| {
+                        if facets::render(ui, facets, filters) {
+                            *omnibox = search::to_query(filters);
+                            *omnibox_error = None;
+                            refine = true;
+                        }
+                    });
+            }
+            if refine {
+                search_again(db, filters, config, results, uifilter, facets, more);
+            }
+
             ui.separator();
+            ui.toggle_value(show_stats, "Statistics");
             if let Some(status) = download.get_status() {
                 *download_status = status;
             }
-            ui.label(format!("Downloaded: {:?}", download_status));
+            ui.label(format!(
+                "Downloaded: {} ({} errors)",
+                download_status.completed, download_status.errors
+            ));
+            if download_status.active > 1 {
+                ui.label(format!("Downloading {} books", download_status.active));
+            }
+            ui.label(download_status.description.as_str());
+            if let Some(progress) = &download_status.progress {
+                render_progress(ui, progress);
+                // nothing else wakes the ui while bytes arrive
+                ctx.request_repaint_after(std::time::Duration::from_millis(250));
+            }
         });
 
+        if let Some(db) = db {
+            egui::Window::new("Catalog statistics")
+                .open(show_stats)
+                .default_width(450.0)
+                .show(ctx, |ui| render_stats(ui, db, stats));
+        }
+
         egui::CentralPanel::default().show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.selectable_value(view, View::Books, "Books");
+                ui.selectable_value(view, View::Authors, "Authors");
+                // the books table may have been sorted since
+                if ui.selectable_value(view, View::Series, "Series").clicked() {
+                    series_view.clear();
+                }
+            });
+            if *view == View::Authors {
+                if let Some(db) = db {
+                    authors::render(ui, authors, db, &filters.collection, download);
+                }
+                return;
+            }
+            let mut load_more = false;
             match results {
-                Ok(books) => render_results_table(ui, books, download, config),
+                Ok(books) if *view == View::Series => {
+                    series::render(ui, series_view, books, download)
+                }
+                Ok(books) if books.is_empty() && !suggestions.is_empty() => {
+                    if let Some(suggestion) = render_suggestions(ui, suggestions) {
+                        *filters = suggestion;
+                        *omnibox = search::to_query(filters);
+                        *pattern_errors = db::invalid_patterns(filters);
+                        search_again(db, filters, config, results, uifilter, facets, more);
+                    }
+                }
+                Ok(books) => {
+                    if more.is_some() {
+                        load_more |= ui.button("Load more").clicked();
+                    }
+                    load_more |= render_results_table(ui, books, download, config);
+                }
                 Err(e) => {
                     ui.label(e.to_string());
-                    ()
                 }
             };
+            if load_more {
+                if let (Some(db), Some(cursor)) = (db.as_ref(), more.take()) {
+                    db.query(page(filters, Some(cursor), config));
+                }
+            }
         });
 
         egui::TopBottomPanel::bottom("bottom_panel").show(ctx, |ui| {
@@ -178,7 +410,7 @@ impl eframe::App for TemplateApp {
     }
 }
 
-fn render_logs(ui: &mut egui::Ui) -> () {
+fn render_logs(ui: &mut egui::Ui) {
     ui.separator();
     let logtext = "".to_string();
     ui.add(egui::TextEdit::multiline(&mut logtext.as_str()));
@@ -186,44 +418,299 @@ fn render_logs(ui: &mut egui::Ui) -> () {
 
 fn read_results(
     results: &mut Result<Vec<db::BookRef>, String>,
-    mut newbooks: Vec<db::BookRef>,
+    newbooks: Vec<db::BookRef>,
     uifilter: &mut UIFilter,
+    facets: &mut Facets,
     deduplicate: bool,
     ctx: &egui::Context,
 ) {
+    if results.is_err() {
+        // no previous results, replace the previous error
+        *results = Ok(Vec::with_capacity(newbooks.len()));
+    }
     if let Ok(bookcache) = results {
-        while newbooks.len() > 0 {
-            let mut newbook = newbooks.pop().unwrap();
+        // batches arrive in sort order, keep it
+        for newbook in newbooks {
+            facets.add(&newbook);
             if deduplicate {
-                filter_update_booklist(uifilter, bookcache, &mut newbook);
+                filter_update_booklist(uifilter, bookcache, &newbook);
             } else {
                 bookcache.push(newbook);
             }
-            // keep redrawing while results are available even if mouse is not moving
-            ctx.request_repaint();
         }
-    } else {
-        // no previous results, just replace the previous error
-        *results = Ok(newbooks);
+        // keep redrawing while results are available even if mouse is not moving
+        ctx.request_repaint();
+    }
+}
+
+// Start over with the first page of the current search
+fn search_again(
+    db: &Option<db::DB>,
+    filters: &db::Params,
+    config: &Config,
+    results: &mut Result<Vec<db::BookRef>, String>,
+    uifilter: &mut UIFilter,
+    facets: &mut Facets,
+    more: &mut Option<db::Cursor>,
+) {
+    if let Some(db) = db {
+        db.cancel();
+        // the side panel says what's wrong with the pattern
+        if !db::invalid_patterns(filters).is_empty() {
+            *results = Err(String::from("Fix the pattern to search"));
+            return;
+        }
+        db.query(page(filters, None, config));
+        *results = Err(String::from("Searching..."));
+        *uifilter = UIFilter::default();
+        *facets = Facets::default();
+        *more = None;
+    }
+}
+
+// the search for one page of results
+fn page(filters: &db::Params, after: Option<db::Cursor>, config: &Config) -> db::Params {
+    db::Params {
+        page_size: config.get::<usize>("pageSize").unwrap_or(1000),
+        after,
+        ..filters.clone()
+    }
+}
+
+// One box for the whole search, with the separate field boxes folded away
+// underneath. Editing either side rewrites the other.
+fn render_omnibox(
+    ui: &mut egui::Ui,
+    omnibox: &mut String,
+    error: &mut Option<search::ParseError>,
+    pattern_errors: &mut Vec<(Field, String)>,
+    filters: &mut db::Params,
+) -> bool {
+    let mut changed = false;
+    let edit = egui::TextEdit::singleline(omnibox)
+        .hint_text(r#"author:pratchett -format:pdf "guards guards""#)
+        .desired_width(f32::INFINITY);
+    if ui.add(edit).changed() {
+        match search::parse(omnibox) {
+            Ok(terms) => {
+                search::apply(terms, filters);
+                *pattern_errors = db::invalid_patterns(filters);
+                *error = None;
+                changed = true;
+            }
+            Err(e) => *error = Some(e),
+        }
+    }
+    if let Some(e) = error {
+        let column = omnibox[..e.position.min(omnibox.len())].chars().count() + 1;
+        ui.colored_label(
+            ui.visuals().error_fg_color,
+            format!("{} (column {})", e.message, column),
+        );
+    }
+
+    let mut ranges_changed = false;
+    ui.horizontal(|ui| {
+        ui.label("Year");
+        ranges_changed |= render_bound(ui, &mut filters.min_year, 1900, 1, "");
+        ui.label("to");
+        ranges_changed |= render_bound(ui, &mut filters.max_year, this_year(), 1, "");
+    });
+    ui.horizontal(|ui| {
+        ui.label("Size");
+        ranges_changed |= render_bound(ui, &mut filters.min_size, 0, 1 << 20, " MB");
+        ui.label("to");
+        ranges_changed |= render_bound(ui, &mut filters.max_size, 5, 1 << 20, " MB");
+    });
+    if ranges_changed {
+        *omnibox = search::to_query(filters);
+        *error = None;
+        changed = true;
+    }
+
+    ui.collapsing("Advanced", |ui| {
+        let mut boxes_changed = false;
+        let regex = &mut filters.regex;
+        for (label, text, field) in [
+            ("Title", &mut filters.title, Field::Title),
+            ("Authors", &mut filters.authors, Field::Authors),
+            ("Series", &mut filters.series, Field::Series),
+            ("Language", &mut filters.language, Field::Language),
+            ("Format", &mut filters.format, Field::Format),
+        ] {
+            boxes_changed |= render_filter(ui, label, text, field, regex, pattern_errors);
+        }
+        if boxes_changed {
+            *omnibox = search::to_query(filters);
+            *error = None;
+            changed = true;
+        }
+    });
+    changed
+}
+
+// An optional range limit: ticked to use it, then dragged to set it.
+// `scale` converts between the shown units and the stored ones; a limit that
+// isn't a whole number of them is shown with decimals rather than cut down.
+fn render_bound(
+    ui: &mut egui::Ui,
+    value: &mut Option<i64>,
+    default: i64,
+    scale: i64,
+    suffix: &str,
+) -> bool {
+    let mut enabled = value.is_some();
+    let mut changed = ui.checkbox(&mut enabled, "").changed();
+    if changed {
+        *value = enabled.then_some(default * scale);
+    }
+    if let Some(value) = value {
+        let mut shown = *value as f64 / scale as f64;
+        let drag = match scale {
+            1 => egui::DragValue::new(&mut shown).max_decimals(0),
+            _ => egui::DragValue::new(&mut shown).speed(0.1).max_decimals(2),
+        };
+        if ui.add(drag.suffix(suffix)).changed() {
+            *value = (shown * scale as f64).round() as i64;
+            changed = true;
+        }
+    }
+    changed
+}
+
+// the year now, near enough for a default
+fn this_year() -> i64 {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    // an average Gregorian year
+    1970 + (seconds / 31_556_952) as i64
+}
+
+fn render_topics(
+    ui: &mut egui::Ui,
+    db: &db::DB,
+    tree: &mut Option<Result<TopicTree, String>>,
+    selected: &mut Vec<i64>,
+) -> bool {
+    let mut changed = false;
+    ui.collapsing("Topics", |ui| match tree {
+        None => {
+            db.topics();
+            *tree = Some(Err(String::from("Loading topics...")));
+        }
+        Some(Err(e)) => {
+            ui.label(e.as_str());
+            // e.g. the file was busy for longer than we waited
+            if ui.button("Retry").clicked() {
+                *tree = None;
+            }
+        }
+        Some(Ok(tree)) => {
+            if let Some(&id) = selected.first() {
+                ui.horizontal(|ui| {
+                    ui.label(format!("In {}", tree.name(id).unwrap_or("?")));
+                    if ui.small_button("x").clicked() {
+                        selected.clear();
+                        changed = true;
+                    }
+                });
+            }
+            egui::ScrollArea::vertical()
+                .max_height(300.0)
+                .show(ui, |ui| changed |= topics::render(ui, tree, selected));
+        }
+    });
+    changed
+}
+
+fn render_stats(ui: &mut egui::Ui, db: &db::DB, stats: &mut Option<Result<db::Stats, String>>) {
+    let mut recount = false;
+    match stats {
+        None => {
+            db.stats(false);
+            *stats = Some(Err(String::from("Counting...")));
+        }
+        Some(Err(e)) => {
+            ui.label(e.as_str());
+            recount = ui.button("Retry").clicked();
+        }
+        Some(Ok(s)) => {
+            recount = ui.button("Count again").clicked();
+            stats::render(ui, s);
+        }
+    }
+    if recount {
+        db.stats(true);
+        *stats = Some(Err(String::from("Counting...")));
+    }
+}
+
+// the suggestion that was clicked, if any
+fn render_suggestions(ui: &mut egui::Ui, suggestions: &[db::Params]) -> Option<db::Params> {
+    let mut picked = None;
+    ui.label("Nothing found. Did you mean:");
+    for suggestion in suggestions {
+        if ui.link(search::to_query(suggestion)).clicked() {
+            picked = Some(suggestion.clone());
+        }
     }
+    picked
 }
 
-fn render_filter(ui: &mut egui::Ui, label: &str, text: &mut String) -> bool {
+// A box for one field, with a toggle to treat its text as a regular expression
+fn render_filter(
+    ui: &mut egui::Ui,
+    label: &str,
+    text: &mut String,
+    field: Field,
+    regex: &mut Vec<Field>,
+    errors: &mut Vec<(Field, String)>,
+) -> bool {
     let mut result = false;
+    let mut edited = false;
+    let mut is_regex = regex.contains(&field);
     ui.horizontal(|ui| {
         ui.label(label.to_owned());
         let e = ui.text_edit_singleline(text);
         result = e.changed();
+        edited = result;
+        if ui
+            .toggle_value(&mut is_regex, ".*")
+            .on_hover_text("Regular expression")
+            .changed()
+        {
+            regex.retain(|f| *f != field);
+            if is_regex {
+                regex.push(field);
+            }
+            result = !text.is_empty();
+            edited = true;
+        }
     });
-    return result;
+    // compiling a pattern every frame is too slow
+    if edited {
+        errors.retain(|(f, _)| *f != field);
+        if is_regex {
+            if let Err(e) = db::compile_pattern(text) {
+                errors.push((field, e.to_string()));
+            }
+        }
+    }
+    if let Some((_, e)) = errors.iter().find(|(f, _)| *f == field) {
+        ui.colored_label(ui.visuals().error_fg_color, e);
+    }
+    result
 }
 
+// returns true when the last rows are on screen, to fetch the next page
 fn render_results_table(
     ui: &mut egui::Ui,
-    books: &mut Vec<db::BookRef>,
+    books: &mut [db::BookRef],
     download: &download::Download,
     config: &config::Config,
-) {
+) -> bool {
+    let mut near_end = false;
     let link_base = config.get::<String>("linkBase").unwrap_or("".to_string());
     let mut tb = TableBuilder::new(ui)
         .max_scroll_height(10_000.0)
@@ -252,6 +739,7 @@ fn render_results_table(
     })
     .body(|body| {
         body.rows(20.0, books.len(), |i, mut row| {
+            near_end |= i + 20 >= books.len();
             render_download_cell(&mut row, download, &books[i]);
             let authors = books[i].authors.as_str();
             let title_query = format!("{} by {}", books[i].title, authors);
@@ -268,8 +756,10 @@ fn render_results_table(
                 format!("{:.0}", books[i].sizeinbytes as f32 / 1024.0).as_str(),
             );
             render_text_cell(&mut row, books[i].format.as_str());
+            render_text_cell(&mut row, books[i].collection.name());
         });
     });
+    near_end
 }
 
 fn render_download_cell(
@@ -277,6 +767,11 @@ fn render_download_cell(
     download: &download::Download,
     book: &db::BookRef,
 ) {
+    row.col(|ui| download_button(ui, download, book));
+}
+
+// download, open or the download's progress, depending on where it's got to
+pub(crate) fn download_button(ui: &mut egui::Ui, download: &download::Download, book: &BookRef) {
     let mut download_status = if let Ok(status) = book.download_status.read() {
         status.clone()
     } else {
@@ -286,15 +781,10 @@ fn render_download_cell(
         download_status = check_downloaded(book);
     }
 
-    row.col(|ui| match download_status {
-        s if s == "" => {
+    match download_status {
+        s if s.is_empty() => {
             if ui.button("download").clicked() {
-                if let Ok(mut status) = book.download_status.write() {
-                    *status = String::from("Queued");
-                }
-                if let Err(_) = download.queue.send(book.clone()) {
-                    log::error!("Failed to send download request");
-                }
+                queue_download(download, book);
             }
         }
         s if s == "Done" => {
@@ -306,10 +796,48 @@ fn render_download_cell(
                 }
             }
         }
+        s if s == "Downloading" => {
+            let progress = book.download_progress.read().ok().and_then(|p| p.clone());
+            match progress {
+                Some(progress) => render_progress(ui, &progress),
+                None => {
+                    ui.label(s);
+                }
+            }
+        }
         s => {
             ui.label(s);
         }
-    });
+    }
+}
+
+fn render_progress(ui: &mut egui::Ui, progress: &download::Progress) {
+    let bar = match progress.fraction() {
+        Some(fraction) => egui::ProgressBar::new(fraction),
+        // no idea how big it is, so the bar just keeps moving
+        None => egui::ProgressBar::new(0.0).animate(true),
+    };
+    ui.add(bar.desired_width(150.0).text(progress.to_string()));
+}
+
+pub(crate) fn queue_download(download: &download::Download, book: &BookRef) {
+    if let Ok(mut status) = book.download_status.write() {
+        *status = String::from("Queued");
+    }
+    if download.queue.send(book.clone()).is_err() {
+        log::error!("Failed to send download request");
+    }
+}
+
+pub(crate) fn is_downloaded(book: &BookRef) -> bool {
+    let status = match book.download_status.read() {
+        Ok(status) => status.clone(),
+        Err(_) => return false,
+    };
+    match status.as_str() {
+        "?" => check_downloaded(book) == "Done",
+        s => s == "Done",
+    }
 }
 
 fn check_downloaded(book: &BookRef) -> String {
@@ -322,20 +850,21 @@ fn check_downloaded(book: &BookRef) -> String {
     if let Ok(mut status_ref) = book.download_status.write() {
         *status_ref = status.clone();
     }
-    return status;
+    status
 }
 
-fn sort_books(col: &&str, books: &mut Vec<db::BookRef>) {
+fn sort_books(col: &&str, books: &mut [db::BookRef]) {
     books.sort_by(|a, b| match *col {
         "Title" => a.title.to_lowercase().cmp(&b.title.to_lowercase()),
         "Authors" => a.authors.to_lowercase().cmp(&b.authors.to_lowercase()),
-        "Series" => compare_series(a.series.as_str(), b.series.as_str()),
+        "Series" => series::compare(a.series.as_str(), b.series.as_str()),
         "Year" => a.year.cmp(&b.year),
         "Language" => a.language.cmp(&b.language),
         "Publisher" => a.publisher.cmp(&b.publisher),
         "Duplicates" => compare_duplicates(&b.duplicates, &a.duplicates),
         "FileSize" => a.sizeinbytes.cmp(&b.sizeinbytes),
         "Format" => a.format.to_lowercase().cmp(&b.format.to_lowercase()),
+        "Collection" => a.collection.name().cmp(b.collection.name()),
         &_ => Ordering::Equal,
     });
 }
@@ -353,7 +882,7 @@ fn render_searchlink_cell(
     query: &str,
 ) {
     let query = utf8_percent_encode(query, NON_ALPHANUMERIC).to_string();
-    if link_base == "" {
+    if link_base.is_empty() {
         render_text_cell(row, text);
     } else {
         let url = format!("{}{}", link_base, query);
@@ -363,21 +892,7 @@ fn render_searchlink_cell(
     }
 }
 
-// parse out the number at the end of the series name
-fn compare_series(a: &str, b: &str) -> Ordering {
-    let a = a.trim();
-    let b = b.trim();
-    let a = a.split_whitespace().last();
-    let b = b.split_whitespace().last();
-    match (a, b) {
-        (Some(a), Some(b)) => match (a.parse::<i32>(), b.parse::<i32>()) {
-            (Ok(a), Ok(b)) => a.cmp(&b),
-            _ => a.cmp(b),
-        },
-        _ => a.cmp(&b),
-    }
-}
-
+// how many copies of each book were folded into its row
 fn compare_duplicates(a: &RwLock<usize>, b: &RwLock<usize>) -> Ordering {
     let a = a.read().unwrap();
     let b = b.read().unwrap();
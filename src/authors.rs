@@ -0,0 +1,150 @@
+use std::{cmp::Ordering, collections::BTreeMap};
+
+use crate::{
+    app::download_button,
+    db::{self, parse_series, BookRef, Collection},
+    download,
+};
+
+// Every author in the collection, narrowed down by typing the start of a
+// name, next to the books of the one picked
+#[derive(Default)]
+pub struct AuthorsView {
+    prefix: String,
+    // what the list was asked for with, to ask again when either changes
+    listed: Option<(Collection, String)>,
+    authors: Option<Result<Vec<(String, i64)>, String>>,
+    author: Option<String>,
+    books: Option<Result<Vec<BookRef>, String>>,
+}
+
+impl AuthorsView {
+    pub fn set_authors(&mut self, authors: Result<Vec<(String, i64)>, String>) {
+        self.authors = Some(authors);
+    }
+
+    pub fn set_books(&mut self, books: Result<Vec<BookRef>, String>) {
+        self.books = Some(books);
+    }
+}
+
+pub fn render(
+    ui: &mut egui::Ui,
+    view: &mut AuthorsView,
+    db: &db::DB,
+    collection: &Collection,
+    download: &download::Download,
+) {
+    let wanted = (collection.clone(), view.prefix.clone());
+    if view.listed.as_ref() != Some(&wanted) {
+        db.authors(collection, &view.prefix);
+        view.listed = Some(wanted);
+    }
+
+    egui::SidePanel::left("authors_list")
+        .resizable(true)
+        .default_width(250.0)
+        .show_inside(ui, |ui| {
+            ui.add(
+                egui::TextEdit::singleline(&mut view.prefix)
+                    .hint_text("Name starts with")
+                    .desired_width(f32::INFINITY),
+            );
+            match &view.authors {
+                None => {
+                    ui.spinner();
+                }
+                Some(Err(e)) => {
+                    ui.label(e.as_str());
+                }
+                Some(Ok(authors)) => {
+                    let mut picked = None;
+                    let row_height = ui.text_style_height(&egui::TextStyle::Body);
+                    egui::ScrollArea::vertical().show_rows(
+                        ui,
+                        row_height,
+                        authors.len(),
+                        |ui, rows| {
+                            for (author, books) in &authors[rows] {
+                                let selected = view.author.as_ref() == Some(author);
+                                if ui
+                                    .selectable_label(selected, format!("{author} ({books})"))
+                                    .clicked()
+                                {
+                                    picked = Some(author.clone());
+                                }
+                            }
+                        },
+                    );
+                    if let Some(author) = picked {
+                        db.bibliography(collection, &author);
+                        view.author = Some(author);
+                        view.books = None;
+                    }
+                }
+            }
+        });
+
+    let author = match &view.author {
+        Some(author) => author,
+        None => {
+            ui.label("Pick an author to see their books");
+            return;
+        }
+    };
+    ui.heading(author.as_str());
+    match &view.books {
+        None => {
+            ui.spinner();
+        }
+        Some(Err(e)) => {
+            ui.label(e.as_str());
+        }
+        Some(Ok(books)) => {
+            egui::ScrollArea::vertical().show(ui, |ui| render_bibliography(ui, books, download));
+        }
+    }
+}
+
+// the books in each series in volume order, then those not in a series
+fn render_bibliography(ui: &mut egui::Ui, books: &[BookRef], download: &download::Download) {
+    let mut series: BTreeMap<&str, Vec<&BookRef>> = BTreeMap::new();
+    for book in books {
+        series
+            .entry(parse_series(&book.series).0)
+            .or_default()
+            .push(book);
+    }
+    let standalone = series.remove("");
+    let groups = series
+        .into_iter()
+        .chain(standalone.map(|books| ("Not in a series", books)));
+    for (name, mut books) in groups {
+        books.sort_by(|a, b| {
+            let volume = |book: &BookRef| parse_series(&book.series).1;
+            match (volume(a), volume(b)) {
+                (Some(a), Some(b)) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+                (a, b) => b.is_some().cmp(&a.is_some()),
+            }
+            .then_with(|| a.title.to_lowercase().cmp(&b.title.to_lowercase()))
+        });
+        egui::CollapsingHeader::new(format!("{} ({})", name, books.len()))
+            .default_open(true)
+            .show(ui, |ui| {
+                egui::Grid::new(name).striped(true).show(ui, |ui| {
+                    for book in books {
+                        match parse_series(&book.series).1 {
+                            Some(volume) => ui.label(volume.to_string()),
+                            None => ui.label(""),
+                        };
+                        download_button(ui, download, book);
+                        ui.label(book.title.as_str());
+                        ui.label(book.year.as_str());
+                        ui.label(book.format.as_str());
+                        ui.label(format!("{:.0} KB", book.sizeinbytes as f32 / 1024.0));
+                        ui.end_row();
+                    }
+                });
+            });
+    }
+}
@@ -1,24 +1,52 @@
 use std::{
+    collections::HashMap,
     path::{Path, PathBuf},
     sync::{
-        atomic::{AtomicBool, Ordering::Relaxed},
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering::Relaxed},
         Arc, RwLock,
     },
     thread,
+    time::{Duration, Instant},
 };
 
 use config::Config;
-use crossbeam::channel::{unbounded, Receiver, Sender};
-use rusqlite::{InterruptHandle, Row};
+use crossbeam::channel::{bounded, unbounded, Receiver, Sender};
+use regex::{Regex, RegexBuilder};
+use rusqlite::{
+    functions::FunctionFlags,
+    types::{Value, ValueRef},
+    InterruptHandle, Row, ToSql,
+};
 
-use crate::config::load_settings;
+use crate::{
+    config::load_settings,
+    download,
+    fold::fold,
+    fuzzy,
+    search::{Field, Term},
+};
 
 pub struct DB {
-    query_send: Sender<Query>,
-    response_receive: Receiver<Result<Vec<BookRef>, String>>,
+    query_send: Sender<Box<Query>>,
+    // every response is tagged with the id of the query it answers
+    response_receive: Receiver<(u64, Response)>,
+    // stops the search; the reader connections carry on
     interrupt: Option<InterruptHandle>,
+    // id of the latest query; anything older is stale
+    generation: Arc<AtomicU64>,
     pub processing: Arc<AtomicBool>,
-    config: Config,
+    // rows the current search has read so far
+    pub produced: Arc<AtomicUsize>,
+    // `_mini` tables that have a full-text index next to them
+    fts_tables: Arc<RwLock<Vec<String>>>,
+    // what the open file contains, empty if it couldn't be opened
+    pub catalog: Catalog,
+    // everything but searches, for the reader threads
+    request_send: Sender<Request>,
+    topic_answers: Answers<Result<Vec<Topic>, String>>,
+    stats_answers: Answers<Result<Stats, String>>,
+    author_answers: Answers<Result<Vec<(String, i64)>, String>>,
+    bibliography_answers: Answers<Result<Vec<BookRef>, String>>,
 }
 
 #[derive(Debug, Default)]
@@ -33,18 +61,105 @@ pub struct Book {
     pub sizeinbytes: i64,
     pub format: String,
     pub ipfs_cid: String,
+    // lower case, empty if the catalog doesn't have it
+    pub md5: String,
     pub duplicates: std::sync::RwLock<usize>,
     pub download_status: std::sync::RwLock<String>,
+    // bytes received so far while it's downloading
+    pub download_progress: std::sync::RwLock<Option<download::Progress>>,
     pub download_path: PathBuf,
 }
 
 // a reference-counted public type for Book
 pub type BookRef = Arc<Book>;
 
+// Work for the reader threads besides searches. `id` tags the answer so that
+// only the one for the latest request of each kind is passed on.
+#[derive(Debug)]
+enum Request {
+    // the non-fiction topics with a count of books in each, from these tables
+    Topics {
+        id: u64,
+        tables: Vec<String>,
+    },
+    // catalog statistics for each file, worked out again if `refresh` or if
+    // they aren't cached yet
+    Stats {
+        id: u64,
+        files: Vec<(String, Vec<(Collection, String)>)>,
+        refresh: bool,
+    },
+    // distinct authors in these tables starting with `prefix`
+    Authors {
+        id: u64,
+        tables: Vec<String>,
+        prefix: String,
+    },
+    // all the rows of a search at once
+    Bibliography {
+        id: u64,
+        query: Box<Query>,
+    },
+}
+
+// Answers to one kind of request from the reader threads
+struct Answers<T> {
+    send: Sender<(u64, T)>,
+    receive: Receiver<(u64, T)>,
+    // id of the latest request; answers to earlier ones are dropped
+    latest: AtomicU64,
+}
+
+impl<T> Answers<T> {
+    fn new() -> Self {
+        let (send, receive) = unbounded();
+        Self {
+            send,
+            receive,
+            latest: AtomicU64::new(0),
+        }
+    }
+
+    fn next_id(&self) -> u64 {
+        self.latest.fetch_add(1, Relaxed) + 1
+    }
+
+    fn get(&self) -> Option<T> {
+        let latest = self.latest.load(Relaxed);
+        while let Ok((id, answer)) = self.receive.try_recv() {
+            if id == latest {
+                return Some(answer);
+            }
+        }
+        None
+    }
+}
+
+type Reply<T> = Sender<(u64, Result<T, String>)>;
+
+// where the reader threads send their answers
+#[derive(Clone)]
+struct Replies {
+    topics: Reply<Vec<Topic>>,
+    stats: Reply<Stats>,
+    authors: Reply<Vec<(String, i64)>>,
+    bibliography: Reply<Vec<BookRef>>,
+    // statistics counted afresh, for the writer to cache in their file
+    cache: Sender<(String, Stats)>,
+    // so the app draws the answer as soon as it comes, not on the next input
+    repaint: Option<egui::Context>,
+}
+
 #[derive(Debug)]
 pub struct Query {
+    pub id: u64,
     pub stmt: String,
+    // named values for the `:placeholders` in `stmt`
+    pub args: Vec<(String, Value)>,
     pub params: Params,
+    // what it was built for, to build it again with other words
+    pub sources: Vec<(String, Collection, Schema)>,
+    pub fts: bool,
 }
 
 #[derive(Debug, Default, Clone, serde::Deserialize, serde::Serialize)]
@@ -57,6 +172,118 @@ pub struct Params {
     pub language: String,
     pub format: String,
     pub deduplicate: bool,
+    pub mode: SearchMode,
+    // match Cyrillic and Greek with Latin letters; the full-text index has
+    // the original scripts, so the tables are searched row by row instead
+    pub transliterate: bool,
+    // boxes holding a regular expression rather than text to find
+    pub regex: Vec<Field>,
+    // omnibox terms that don't fit in the boxes above: free text, phrases,
+    // exclusions and other fields
+    pub terms: Vec<Term>,
+    // inclusive bounds on the publication year and on the file size in bytes
+    pub min_year: Option<i64>,
+    pub max_year: Option<i64>,
+    pub min_size: Option<i64>,
+    pub max_size: Option<i64>,
+    // exactly this author, for a bibliography
+    #[serde(skip)]
+    pub author: Option<String>,
+    // non-fiction topic ids to restrict to: the chosen topic first, then all
+    // of its subtopics
+    pub topics: Vec<i64>,
+    // in fuzzy mode, words in the catalog like each searched word, which
+    // match as well as the word itself
+    #[serde(skip)]
+    pub similar: Vec<(String, Vec<String>)>,
+    // rows per page, 0 for everything at once
+    #[serde(skip)]
+    pub page_size: usize,
+    // continue after this row instead of starting from the top
+    #[serde(skip)]
+    pub after: Option<Cursor>,
+}
+
+// The sort key of the last row on a page, used to fetch the next one
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cursor(Vec<Value>);
+
+pub enum Response {
+    Books(Vec<BookRef>),
+    // the page is complete; `Some` if there may be more rows after it
+    PageEnd(Option<Cursor>),
+    Error(String),
+    // the same search with misspelt words replaced, after it found nothing
+    Suggestions(Vec<Params>),
+}
+
+// The catalog files from Settings.yaml; a lone `dbPath` still works
+pub fn db_paths(config: &Config) -> Vec<String> {
+    match config.get::<Vec<String>>("dbPaths") {
+        Ok(paths) => paths,
+        Err(_) => config.get::<String>("dbPath").into_iter().collect(),
+    }
+}
+
+// Book counts across the whole catalog, as (group, value, count): e.g.
+// ("Language", "English", 1234). "Size" counts bytes instead of books.
+#[derive(Debug, Default, Clone)]
+pub struct Stats {
+    pub rows: Vec<(String, String, i64)>,
+}
+
+impl Stats {
+    // the values in a group, biggest first
+    pub fn group(&self, group: &str) -> Vec<(&str, i64)> {
+        let mut values: Vec<(&str, i64)> = self
+            .rows
+            .iter()
+            .filter(|(g, _, _)| g == group)
+            .map(|(_, value, count)| (value.as_str(), *count))
+            .collect();
+        values.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        values
+    }
+
+    // add another file's numbers to these
+    fn merge(&mut self, other: Stats) {
+        for (group, value, count) in other.rows {
+            match self
+                .rows
+                .iter_mut()
+                .find(|(g, v, _)| g == &group && v == &value)
+            {
+                Some(row) => row.2 += count,
+                None => self.rows.push((group, value, count)),
+            }
+        }
+    }
+}
+
+// A row of the `topics` table
+#[derive(Debug, Clone, PartialEq)]
+pub struct Topic {
+    pub id: i64,
+    // the topic this one is under, or itself at the top level
+    pub parent: i64,
+    // the full path, e.g. "Technology\Aerospace Equipment"
+    pub name: String,
+    // books filed directly under this topic, not counting subtopics
+    pub count: usize,
+}
+
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, serde::Deserialize, serde::Serialize)]
+pub enum SearchMode {
+    // substring match anywhere in the field; works on every database
+    #[default]
+    Like,
+    // whole-word and prefix match through the FTS5 index, sorted by author
+    FullText,
+    // as FullText, but the best bm25 matches come first
+    Relevance,
+    // as FullText, but also matching words spelt a bit differently, the
+    // closest matches first
+    Fuzzy,
 }
 
 #[derive(Debug, Default, Clone, Eq, PartialEq, serde::Deserialize, serde::Serialize)]
@@ -64,169 +291,825 @@ pub enum Collection {
     #[default]
     Fiction,
     NonFiction,
+    // every collection in the catalog
+    All,
+}
+
+impl Collection {
+    // table name prefix
+    fn table(&self) -> &'static str {
+        match self {
+            Collection::Fiction => "fiction",
+            Collection::NonFiction => "non_fiction",
+            Collection::All => "",
+        }
+    }
+
+    fn from_table(table: &str) -> Self {
+        match table {
+            "non_fiction" => Collection::NonFiction,
+            _ => Collection::Fiction,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Collection::Fiction => "Fiction",
+            Collection::NonFiction => "Nonfiction",
+            Collection::All => "All",
+        }
+    }
+}
+
+// How a collection is laid out in the catalog file
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Schema {
+    // `{0}_mini`, made by `import` or the compress scripts
+    Mini,
+    // `{0}_mini` made before the compress scripts kept the md5
+    MiniWithoutMd5,
+    // `{0}` joined to `{0}_hashes`, as restored from the dump
+    Full,
+}
+
+impl Schema {
+    pub fn is_mini(self) -> bool {
+        self != Schema::Full
+    }
+
+    // the md5 as read by `row_to_book`
+    fn md5_column(self) -> &'static str {
+        match self {
+            Schema::Mini => "f.MD5",
+            Schema::MiniWithoutMd5 => "NULL",
+            Schema::Full => "fh.md5",
+        }
+    }
+}
+
+fn has_column(
+    connection: &rusqlite::Connection,
+    alias: &str,
+    table: &str,
+    column: &str,
+) -> rusqlite::Result<bool> {
+    connection.query_row(
+        &format!("SELECT count(*) > 0 FROM pragma_table_info(?1, '{alias}') WHERE name = ?2 COLLATE NOCASE"),
+        [table, column],
+        |row| row.get(0),
+    )
+}
+
+// One catalog file and what it contains
+#[derive(Debug, Clone)]
+pub struct Source {
+    // the schema name it's attached under
+    pub alias: String,
+    pub path: String,
+    pub fiction: Option<Schema>,
+    pub non_fiction: Option<Schema>,
+    // whether the non-fiction topic names are there
+    pub topics: bool,
+}
+
+impl Source {
+    // look at which tables exist to see which collections we can search
+    fn detect(
+        connection: &rusqlite::Connection,
+        alias: &str,
+        path: &str,
+    ) -> rusqlite::Result<Self> {
+        let mut stmt = connection.prepare(&format!(
+            "SELECT name FROM {alias}.sqlite_master WHERE type = 'table'"
+        ))?;
+        let tables = stmt
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        let has = |name: &str| tables.iter().any(|t| t.eq_ignore_ascii_case(name));
+        let schema = |collection: Collection| {
+            let table = collection.table();
+            if has(&format!("{table}_mini")) {
+                match has_column(connection, alias, &format!("{table}_mini"), "MD5") {
+                    Ok(true) => Some(Schema::Mini),
+                    _ => Some(Schema::MiniWithoutMd5),
+                }
+            } else if has(table) && has(&format!("{table}_hashes")) {
+                Some(Schema::Full)
+            } else {
+                None
+            }
+        };
+        Ok(Self {
+            alias: alias.to_string(),
+            path: path.to_string(),
+            fiction: schema(Collection::Fiction),
+            non_fiction: schema(Collection::NonFiction),
+            topics: has("topics"),
+        })
+    }
+
+    pub fn schema(&self, collection: &Collection) -> Option<Schema> {
+        match collection {
+            Collection::Fiction => self.fiction,
+            Collection::NonFiction => self.non_fiction,
+            Collection::All => None,
+        }
+    }
+}
+
+// Everything in the catalog files from `dbPaths`
+#[derive(Debug, Default, Clone)]
+pub struct Catalog {
+    // in the order they're listed
+    pub sources: Vec<Source>,
+    // files that couldn't be used and why, to show in the UI
+    pub warnings: Vec<String>,
+}
+
+impl Catalog {
+    // Attach each file to the connection as `db0`, `db1`, ... and see what's in it
+    fn open(connection: &rusqlite::Connection, paths: &[String]) -> Self {
+        let mut catalog = Catalog::default();
+        if paths.is_empty() {
+            catalog
+                .warnings
+                .push("No dbPaths in Settings.yaml".to_string());
+        }
+        for (i, path) in paths.iter().enumerate() {
+            if !Path::new(path).exists() {
+                catalog.warnings.push(format!("{path} does not exist"));
+                continue;
+            }
+            let alias = format!("db{i}");
+            let source = connection
+                .execute(&format!("ATTACH DATABASE ?1 AS {alias}"), [path])
+                .and_then(|_| Source::detect(connection, &alias, path));
+            match source {
+                Ok(source) => {
+                    if source.fiction.is_none() && source.non_fiction.is_none() {
+                        catalog
+                            .warnings
+                            .push(format!("{path} has no fiction or non-fiction tables"));
+                    }
+                    catalog.sources.push(source);
+                }
+                Err(e) => catalog.warnings.push(format!("Can't open {path}: {e}")),
+            }
+        }
+        for warning in &catalog.warnings {
+            log::error!("Error opening database: {}", warning);
+        }
+        catalog
+    }
+
+    // the files and layouts to search for a collection, or for every
+    // collection in every file for `All`
+    pub fn tables(&self, collection: &Collection) -> Vec<(String, Collection, Schema)> {
+        let collections = match collection {
+            Collection::All => vec![Collection::Fiction, Collection::NonFiction],
+            collection => vec![collection.clone()],
+        };
+        let mut tables = Vec::new();
+        for source in &self.sources {
+            for collection in &collections {
+                if let Some(schema) = source.schema(collection) {
+                    tables.push((source.alias.clone(), collection.clone(), schema));
+                }
+            }
+        }
+        tables
+    }
+
+    pub fn collections(&self) -> Vec<Collection> {
+        [Collection::Fiction, Collection::NonFiction]
+            .into_iter()
+            .filter(|c| !self.tables(c).is_empty())
+            .collect()
+    }
+
+    pub fn topics(&self) -> bool {
+        self.sources.iter().any(|s| s.topics)
+    }
+}
+
+// connections for the side queries, so they don't wait for a long search
+const READERS: usize = 2;
+// how long to wait for another connection to finish writing, e.g. an index
+const BUSY_TIMEOUT: Duration = Duration::from_secs(60);
+
+// An in-memory connection with every catalog file attached, so a search can
+// cover all of them at once, and with the SQL functions added
+fn connect(paths: &[String]) -> rusqlite::Result<(rusqlite::Connection, Catalog)> {
+    let connection = rusqlite::Connection::open_in_memory()?;
+    register_functions(&connection)?;
+    connection.busy_timeout(BUSY_TIMEOUT)?;
+    let catalog = Catalog::open(&connection, paths);
+    Ok((connection, catalog))
 }
 
 impl DB {
-    // open a new DB connection.
-    pub fn new() -> Self {
-        let (query_send, query_receive) = unbounded::<Query>();
-        let (response_send, response_receive) = unbounded::<Result<Vec<BookRef>, String>>();
-
-        let processing = Arc::new(AtomicBool::new(false));
-
-        let config = load_settings();
-        let conn = config.get::<String>("dbPath").unwrap_or("".to_string());
-
-        // check that file exists
-        if !std::path::Path::new(&conn).exists() {
-            log::error!("Error opening database: {} does not exist", conn);
-            return Self {
-                query_send,
-                response_receive,
-                interrupt: None,
-                processing,
-                config,
-            };
+    // Open the catalog on a search connection and a few read-only ones for
+    // everything else, each with a thread of its own. `ctx` is repainted
+    // whenever a side query is answered.
+    pub fn new(ctx: egui::Context) -> Self {
+        let (query_send, query_receive) = unbounded::<Box<Query>>();
+        // bounded so the worker waits for the UI instead of piling up rows
+        let (response_send, response_receive) = bounded::<(u64, Response)>(RESPONSE_QUEUE);
+        let (request_send, request_receive) = unbounded::<Request>();
+        let mut db = Self {
+            query_send,
+            response_receive,
+            interrupt: None,
+            generation: Arc::new(AtomicU64::new(0)),
+            processing: Arc::new(AtomicBool::new(false)),
+            produced: Arc::new(AtomicUsize::new(0)),
+            fts_tables: Arc::new(RwLock::new(Vec::new())),
+            catalog: Catalog::default(),
+            request_send,
+            topic_answers: Answers::new(),
+            stats_answers: Answers::new(),
+            author_answers: Answers::new(),
+            bibliography_answers: Answers::new(),
+        };
+
+        let paths = db_paths(&load_settings());
+        let connection = match connect(&paths) {
+            Ok((connection, catalog)) => {
+                log::info!("Catalog: {:?}", catalog);
+                db.catalog = catalog;
+                connection
+            }
+            Err(e) => {
+                log::error!("Error opening database: {}", e);
+                db.catalog.warnings.push(e.to_string());
+                return db;
+            }
+        };
+        db.interrupt = Some(connection.get_interrupt_handle());
+
+        // statistics are cached in the catalog files from a connection of their
+        // own, so the readers stay query-only
+        let (cache_send, cache_receive) = unbounded::<(String, Stats)>();
+        match connect(&paths) {
+            Ok((writer, _)) => {
+                thread::spawn(move || {
+                    for (alias, stats) in cache_receive {
+                        if let Err(e) = save_stats(&writer, &alias, &stats) {
+                            log::warn!("Error caching statistics for {}: {}", alias, e);
+                        }
+                    }
+                });
+            }
+            Err(e) => log::error!("Error opening the statistics connection: {}", e),
         }
 
-        let connection = rusqlite::Connection::open(&conn);
-        if let Err(e) = connection {
-            log::error!("Error opening database: {}", e);
-            return Self {
-                query_send,
-                response_receive,
-                interrupt: None,
-                processing,
-                config,
-            };
+        let replies = Replies {
+            topics: db.topic_answers.send.clone(),
+            stats: db.stats_answers.send.clone(),
+            authors: db.author_answers.send.clone(),
+            bibliography: db.bibliography_answers.send.clone(),
+            cache: cache_send,
+            repaint: Some(ctx),
+        };
+        for _ in 0..READERS {
+            let reader = connect(&paths).and_then(|(reader, _)| {
+                reader.pragma_update(None, "query_only", true)?;
+                Ok(reader)
+            });
+            match reader {
+                Ok(reader) => {
+                    let requests = request_receive.clone();
+                    let replies = replies.clone();
+                    thread::spawn(move || serve_requests(&reader, &requests, &replies));
+                }
+                Err(e) => log::error!("Error opening a reader connection: {}", e),
+            }
         }
-        let connection = connection.unwrap();
-        let interrupt = Some(connection.get_interrupt_handle());
 
-        let processing_clone = processing.clone();
+        let processing_clone = db.processing.clone();
+        let generation_clone = db.generation.clone();
+        let produced_clone = db.produced.clone();
+        let fts_clone = db.fts_tables.clone();
+        let mini_tables: Vec<(String, String)> = db
+            .catalog
+            .tables(&Collection::All)
+            .into_iter()
+            .filter(|(_, _, schema)| schema.is_mini())
+            .map(|(alias, collection, _)| (alias, format!("{}_mini", collection.table())))
+            .collect();
 
-        // queries run in a separate thread
+        // searches run in a separate thread
         // https://doc.rust-lang.org/rust-by-example/std_misc/channels.html
-        thread::spawn(move || loop {
+        thread::spawn(move || {
+            // building the index takes a while the first time, show the spinner
+            processing_clone.store(true, Relaxed);
+            // `_mini` tables with a trigram index of their words
+            let mut trigram_tables = Vec::new();
+            for (alias, table) in mini_tables {
+                match ensure_fts(&connection, &alias, &table) {
+                    Ok(true) => {
+                        if let Ok(mut tables) = fts_clone.write() {
+                            tables.push(format!("{alias}.{table}"));
+                        }
+                        match fuzzy::ensure_trigrams(&connection, &alias, &table) {
+                            Ok(()) => trigram_tables.push(format!("{alias}.{table}")),
+                            Err(e) => log::warn!("No fuzzy index for {}.{}: {}", alias, table, e),
+                        }
+                    }
+                    Ok(false) => {}
+                    Err(e) => log::warn!("No full-text index for {}.{}: {}", alias, table, e),
+                }
+            }
             processing_clone.store(false, Relaxed);
-            if let Ok(query) = query_receive.recv() {
+            while let Ok(query) = query_receive.recv() {
+                if query.id != generation_clone.load(Relaxed) {
+                    // cancelled before it started
+                    continue;
+                }
                 processing_clone.store(true, Relaxed);
-                if let Err(e) = start_query(&connection, &query, &response_send) {
-                    if let Err(e) = response_send.send(Err(e.to_string())) {
-                        log::error!("Error sending error: {}", e);
+                let mut query = *query;
+                let tables = with_trigrams(&query, &trigram_tables);
+                if query.params.mode == SearchMode::Fuzzy && query.fts {
+                    query = fuzzy_query(&connection, &tables, query);
+                }
+                match start_query(
+                    &connection,
+                    &query,
+                    &response_send,
+                    &generation_clone,
+                    &produced_clone,
+                ) {
+                    // nothing found; offer the search with other spellings
+                    Ok(0) if query.params.after.is_none() && !tables.is_empty() => {
+                        match suggest(&connection, &tables, &query.params) {
+                            Ok(suggestions) if !suggestions.is_empty() => {
+                                let response = Response::Suggestions(suggestions);
+                                if let Err(e) = response_send.send((query.id, response)) {
+                                    log::error!("Error sending suggestions: {}", e);
+                                }
+                            }
+                            Ok(_) => {}
+                            Err(e) => log::warn!("No suggestions: {}", e),
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        // an interrupted query fails, but nobody is waiting for it any more
+                        if query.id == generation_clone.load(Relaxed) {
+                            let error = Response::Error(e.to_string());
+                            if let Err(e) = response_send.send((query.id, error)) {
+                                log::error!("Error sending error: {}", e);
+                            }
+                        }
                     }
                 }
+                processing_clone.store(false, Relaxed);
             }
         });
 
-        Self {
-            query_send,
-            response_receive,
-            interrupt,
-            processing,
-            config,
-        }
+        db
     }
 
-    // add a query to the queue for the db thread to process
+    // add a query to the queue for the search thread to process
     pub fn query(&self, params: Params) {
-        // https://www.sqlite.org/fts5.html
-        // search the fiction_fts table for query
-        let stmt = if self.config.get::<bool>("compressedDb").unwrap_or(false) {
-            format!("
-            SELECT title, author as authors, series, year, language, publisher, filesize as sizeinbytes, extension as format, ipfs_cid
-            FROM {0}_mini f
-            WHERE 
-                f.title LIKE '%'||:title||'%' AND 
-                f.author LIKE '%'||:authors||'%' AND
-                f.series LIKE '%'||:series||'%' AND
-                f.language LIKE '%'||:language||'%' AND
-                f.extension LIKE '%'||:format||'%'
-            ORDER BY f.author, f.title, f.filesize
-            ", match params.collection {
-                Collection::NonFiction => "non_fiction",
-                _ => "fiction",
-            })
-        } else {
-            format!("
-            SELECT f.title, f.author as authors, f.series, f.year, f.language, f.publisher, f.filesize as sizeinbytes, f.extension as format, fh.ipfs_cid as ipfs_cid
-            FROM {0} f
-            join {0}_hashes as fh on LOWER(f.md5) = fh.md5
-            WHERE 
-                f.title LIKE '%'||:title||'%' AND 
-                f.author LIKE '%'||:authors||'%' AND
-                f.series LIKE '%'||:series||'%' AND
-                f.language LIKE '%'||:language||'%' AND
-                f.extension LIKE '%'||:format||'%'
-            ORDER BY f.author, f.title, f.filesize
-            ", match params.collection {
-                Collection::NonFiction => "non_fiction",
-                _ => "fiction",
-            })
+        let mut query = match self.build(params) {
+            Some(query) => query,
+            None => return,
         };
-        if let Err(e) = self.query_send.send(Query { stmt, params }) {
+        query.id = self.generation.fetch_add(1, Relaxed) + 1;
+        if query.params.after.is_none() {
+            self.produced.store(0, Relaxed);
+        }
+        if let Err(e) = self.query_send.send(Box::new(query)) {
             log::error!("Error enqueueing query: {}", e);
         }
     }
 
-    // see if there's a result available from the db thread
-    pub fn get_result(&self) -> Option<Result<Vec<BookRef>, String>> {
-        match self.response_receive.try_recv() {
-            Ok(Ok(books)) => Some(Ok(books)),
-            Ok(Err(err)) => Some(Err(err)),
-            Err(_) => None,
+    // the SQL for a search of whichever tables hold the collection
+    fn build(&self, params: Params) -> Option<Query> {
+        let sources = self.catalog.tables(&params.collection);
+        if sources.is_empty() {
+            log::error!("No {:?} tables in the catalog", params.collection);
+            return None;
+        }
+        // the index is used only if every collection has one
+        let fts = sources.iter().all(|(alias, collection, schema)| {
+            schema.is_mini()
+                && match self.fts_tables.read() {
+                    Ok(tables) => tables
+                        .iter()
+                        .any(|t| t == &format!("{alias}.{}_mini", collection.table())),
+                    Err(_) => false,
+                }
+        });
+        Some(build_query(params, &sources, fts))
+    }
+
+    // Ask for the authors in a collection whose names start with `prefix`,
+    // ignoring case. The answer comes from `get_authors`.
+    pub fn authors(&self, collection: &Collection, prefix: &str) {
+        let tables = self
+            .catalog
+            .tables(collection)
+            .into_iter()
+            .map(|(alias, collection, schema)| {
+                format!("{alias}.{}", books_table(&collection, schema))
+            })
+            .collect();
+        let request = Request::Authors {
+            id: self.author_answers.next_id(),
+            tables,
+            prefix: prefix.to_string(),
+        };
+        if let Err(e) = self.request_send.send(request) {
+            log::error!("Error enqueueing authors: {}", e);
+        }
+    }
+
+    // Ask for every book by exactly this author; the answer comes from
+    // `get_bibliography`.
+    pub fn bibliography(&self, collection: &Collection, author: &str) {
+        let params = Params {
+            collection: collection.clone(),
+            author: Some(author.to_string()),
+            ..Default::default()
+        };
+        if let Some(query) = self.build(params) {
+            let request = Request::Bibliography {
+                id: self.bibliography_answers.next_id(),
+                query: Box::new(query),
+            };
+            if let Err(e) = self.request_send.send(request) {
+                log::error!("Error enqueueing bibliography: {}", e);
+            }
+        }
+    }
+
+    // ask for the non-fiction topic tree; the answer comes from `get_topics`
+    pub fn topics(&self) {
+        let tables = self
+            .catalog
+            .tables(&Collection::NonFiction)
+            .into_iter()
+            .map(|(alias, collection, schema)| {
+                format!("{alias}.{}", books_table(&collection, schema))
+            })
+            .collect();
+        let id = self.topic_answers.next_id();
+        if let Err(e) = self.request_send.send(Request::Topics { id, tables }) {
+            log::error!("Error enqueueing topics: {}", e);
+        }
+    }
+
+    // ask for the catalog statistics; the answer comes from `get_stats`
+    pub fn stats(&self, refresh: bool) {
+        let files = self
+            .catalog
+            .sources
+            .iter()
+            .map(|source| {
+                let tables = [Collection::Fiction, Collection::NonFiction]
+                    .into_iter()
+                    .filter_map(|c| {
+                        let schema = source.schema(&c)?;
+                        let table = books_table(&c, schema);
+                        Some((c, table))
+                    })
+                    .collect();
+                (source.alias.clone(), tables)
+            })
+            .collect();
+        let id = self.stats_answers.next_id();
+        if let Err(e) = self
+            .request_send
+            .send(Request::Stats { id, files, refresh })
+        {
+            log::error!("Error enqueueing stats: {}", e);
         }
     }
 
-    // stop the currently executing query.
-    // It is also a good idea to drain the receive queue
-    // (keep calling get_result until nothing is left).
-    pub fn interrupt(&self) {
+    // see if there's a result available from the search thread
+    // Responses to earlier queries are dropped here.
+    pub fn get_result(&self) -> Option<Response> {
+        let current = self.generation.load(Relaxed);
+        while let Ok((id, response)) = self.response_receive.try_recv() {
+            if id == current {
+                return Some(response);
+            }
+        }
+        None
+    }
+
+    pub fn get_topics(&self) -> Option<Result<Vec<Topic>, String>> {
+        self.topic_answers.get()
+    }
+
+    pub fn get_stats(&self) -> Option<Result<Stats, String>> {
+        self.stats_answers.get()
+    }
+
+    // (author, number of books), in order of name
+    pub fn get_authors(&self) -> Option<Result<Vec<(String, i64)>, String>> {
+        self.author_answers.get()
+    }
+
+    pub fn get_bibliography(&self) -> Option<Result<Vec<BookRef>, String>> {
+        self.bibliography_answers.get()
+    }
+
+    // Stop the search that is running and drop everything queued or already
+    // sent for it. Side queries run on other connections and carry on.
+    pub fn cancel(&self) {
+        self.generation.fetch_add(1, Relaxed);
         if let Some(interrupt) = &self.interrupt {
             interrupt.interrupt();
         }
     }
 }
 
+// Answer side queries on a reader connection until the app closes
+fn serve_requests(
+    connection: &rusqlite::Connection,
+    requests: &Receiver<Request>,
+    replies: &Replies,
+) {
+    while let Ok(request) = requests.recv() {
+        let sent = match request {
+            Request::Topics { id, tables } => {
+                let topics = load_topics(connection, &tables).map_err(|e| e.to_string());
+                replies.topics.send((id, topics)).is_ok()
+            }
+            Request::Stats { id, files, refresh } => {
+                let mut stats = Stats::default();
+                let mut result = Ok(());
+                for (alias, tables) in &files {
+                    match load_stats(connection, alias, tables, refresh) {
+                        Ok((file_stats, counted)) => {
+                            // the readers are query-only, so the writer keeps them
+                            if counted {
+                                let _ = replies.cache.send((alias.clone(), file_stats.clone()));
+                            }
+                            stats.merge(file_stats)
+                        }
+                        Err(e) => {
+                            result = Err(e.to_string());
+                            break;
+                        }
+                    }
+                }
+                replies.stats.send((id, result.map(|_| stats))).is_ok()
+            }
+            Request::Authors { id, tables, prefix } => {
+                let authors = load_authors(connection, &tables, &prefix).map_err(|e| e.to_string());
+                replies.authors.send((id, authors)).is_ok()
+            }
+            Request::Bibliography { id, query } => {
+                let books = load_books(connection, &query).map_err(|e| e.to_string());
+                replies.bibliography.send((id, books)).is_ok()
+            }
+        };
+        if !sent {
+            // the DB is gone
+            break;
+        }
+        if let Some(ctx) = &replies.repaint {
+            ctx.request_repaint();
+        }
+    }
+}
+
 fn start_query(
     connection: &rusqlite::Connection,
     query: &Query,
-    response_send: &Sender<Result<Vec<BookRef>, String>>,
-) -> Result<(), Box<dyn std::error::Error>> {
+    response_send: &Sender<(u64, Response)>,
+    generation: &AtomicU64,
+    produced: &AtomicUsize,
+) -> Result<usize, Box<dyn std::error::Error>> {
     let mut stmt = connection.prepare(&query.stmt)?;
-    let rows = stmt.query(rusqlite::named_params!(
-        ":title": query.params.title,
-        ":authors": query.params.authors,
-        ":series": query.params.series,
-        ":language": query.params.language,
-        ":format": query.params.format,
-    ));
+    let args: Vec<(&str, &dyn ToSql)> = query
+        .args
+        .iter()
+        .map(|(name, value)| (name.as_str(), value as &dyn ToSql))
+        .collect();
+    let mut rows = stmt.query(args.as_slice())?;
     let config = load_settings();
-    let mut rows = rows?.mapped(|row| row_to_book(&config, query, row));
-    loop {
-        match rows.next() {
-            Some(Ok(book)) => response_send.send(Ok(vec![book]))?,
-            Some(Err(e)) => return Err(e.into()),
-            None => break Ok(()),
+    let mut count = 0;
+    let mut last = None;
+    let mut batch = Vec::new();
+    let mut batch_size = FIRST_BATCH;
+    let mut batch_started = Instant::now();
+    while let Some(row) = rows.next()? {
+        if generation.load(Relaxed) != query.id {
+            // a newer query has been sent, stop streaming this one
+            return Ok(count);
+        }
+        batch.push(row_to_book(&config, row)?);
+        produced.fetch_add(1, Relaxed);
+        count += 1;
+        last = Some(row_key(row)?);
+        // start small so the first rows show up straight away, then grow so
+        // big results don't cost a message per handful of rows
+        if batch.len() >= batch_size || batch_started.elapsed() >= BATCH_TIME {
+            response_send.send((query.id, Response::Books(std::mem::take(&mut batch))))?;
+            batch_size = (batch_size * 2).min(MAX_BATCH);
+            batch_started = Instant::now();
+        }
+    }
+    if !batch.is_empty() {
+        response_send.send((query.id, Response::Books(batch)))?;
+    }
+    // a short page means we've reached the end
+    let more = match query.params.page_size {
+        n if n > 0 && count == n => last,
+        _ => None,
+    };
+    response_send.send((query.id, Response::PageEnd(more)))?;
+    Ok(count)
+}
+
+// the `_mini` tables a search covers that have a trigram index
+fn with_trigrams(query: &Query, trigram_tables: &[String]) -> Vec<String> {
+    query
+        .sources
+        .iter()
+        .map(|(alias, collection, _)| format!("{alias}.{}_mini", collection.table()))
+        .filter(|table| trigram_tables.contains(table))
+        .collect()
+}
+
+// other spellings of each word tried in fuzzy mode
+const FUZZY_WORDS: usize = 5;
+
+// The search built again to match the catalog's words that are like the
+// searched ones as well
+fn fuzzy_query(connection: &rusqlite::Connection, tables: &[String], query: Query) -> Query {
+    let mut params = query.params.clone();
+    params.similar.clear();
+    for word in searched_words(&query.params) {
+        match fuzzy::similar_words(connection, tables, word) {
+            Ok(words) => {
+                let normalized = fuzzy::normalize(word);
+                let others = words
+                    .into_iter()
+                    .map(|(other, _, _)| other)
+                    .filter(|other| *other != normalized)
+                    .take(FUZZY_WORDS)
+                    .collect();
+                params.similar.push((word.to_string(), others));
+            }
+            Err(e) => log::warn!("No words like {}: {}", word, e),
         }
     }
+    Query {
+        id: query.id,
+        ..build_query(params, &query.sources, query.fts)
+    }
 }
 
-fn row_to_book(config: &Config, query: &Query, row: &Row<'_>) -> Result<BookRef, rusqlite::Error> {
-    let path = download_path(&config, &row.get(1)?, &row.get(0)?, &row.get(7)?);
+// "did you mean" searches offered after nothing was found
+const SUGGESTIONS: usize = 3;
+
+// The search with each word that isn't in the catalog swapped for the closest
+// ones that are: the closest of all first, then the next closest, and so on
+fn suggest(
+    connection: &rusqlite::Connection,
+    tables: &[String],
+    params: &Params,
+) -> rusqlite::Result<Vec<Params>> {
+    let mut choices: Vec<(&str, Vec<String>)> = Vec::new();
+    for word in searched_words(params) {
+        let normalized = fuzzy::normalize(word);
+        let similar = fuzzy::similar_words(connection, tables, word)?;
+        if similar.iter().any(|(other, _, _)| *other == normalized) {
+            // spelt like it is in the catalog already
+            continue;
+        }
+        let others: Vec<String> = similar
+            .into_iter()
+            .take(SUGGESTIONS)
+            .map(|(other, _, _)| other)
+            .collect();
+        if !others.is_empty() {
+            choices.push((word, others));
+        }
+    }
+    if choices.is_empty() {
+        return Ok(vec![]);
+    }
+    let mut picks: Vec<Vec<&str>> = (0..SUGGESTIONS)
+        .map(|i| {
+            choices
+                .iter()
+                .map(|(_, others)| others[i.min(others.len() - 1)].as_str())
+                .collect()
+        })
+        .collect();
+    // words with fewer choices repeat their last one
+    picks.dedup();
+    Ok(picks
+        .into_iter()
+        .map(|pick| {
+            let swaps: HashMap<&str, &str> =
+                choices.iter().map(|(word, _)| *word).zip(pick).collect();
+            replace_words(params, &swaps)
+        })
+        .collect())
+}
+
+// terms whose words go to the full-text index, so can be spelt differently
+fn fuzzy_term(term: &Term) -> bool {
+    !term.negated && !term.phrase && fts_columns(term.field).is_some()
+}
+
+// the words searched for in titles, authors and series, each once
+fn searched_words(params: &Params) -> Vec<&str> {
+    let texts = [
+        (Field::Title, &params.title),
+        (Field::Authors, &params.authors),
+        (Field::Series, &params.series),
+    ]
+    .into_iter()
+    .filter(|(field, _)| !params.regex.contains(field))
+    .map(|(_, text)| text);
+    let terms = params.terms.iter().filter(|t| fuzzy_term(t));
+    let mut words: Vec<&str> = Vec::new();
+    for word in texts
+        .chain(terms.map(|t| &t.text))
+        .flat_map(|text| text.split_whitespace())
+    {
+        if !words.contains(&word) {
+            words.push(word);
+        }
+    }
+    words
+}
+
+// the search with some of its words swapped for others
+fn replace_words(params: &Params, swaps: &HashMap<&str, &str>) -> Params {
+    let swap = |text: &str| {
+        text.split_whitespace()
+            .map(|word| *swaps.get(word).unwrap_or(&word))
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    let mut params = params.clone();
+    let regex = params.regex.clone();
+    for (field, text) in [
+        (Field::Title, &mut params.title),
+        (Field::Authors, &mut params.authors),
+        (Field::Series, &mut params.series),
+    ] {
+        if !regex.contains(&field) {
+            *text = swap(text);
+        }
+    }
+    for term in params.terms.iter_mut().filter(|t| fuzzy_term(t)) {
+        term.text = swap(&term.text);
+    }
+    params.similar.clear();
+    params
+}
+
+// the ORDER BY values that follow the book columns in every search
+fn row_key(row: &Row<'_>) -> rusqlite::Result<Cursor> {
+    let mut key = Vec::new();
+    for i in BOOK_COLUMNS..row.as_ref().column_count() {
+        key.push(row.get::<_, Value>(i)?);
+    }
+    Ok(Cursor(key))
+}
+
+// batches of rows waiting for the UI before the worker blocks
+const RESPONSE_QUEUE: usize = 8;
+const FIRST_BATCH: usize = 50;
+const MAX_BATCH: usize = 5000;
+// send what we have after this long even if the batch isn't full
+const BATCH_TIME: Duration = Duration::from_millis(50);
+
+// columns read by `row_to_book`
+const BOOK_COLUMNS: usize = 11;
+
+fn row_to_book(config: &Config, row: &Row<'_>) -> Result<BookRef, rusqlite::Error> {
+    // empty fields are sometimes NULL rather than ''
+    let text = |i| -> rusqlite::Result<String> {
+        Ok(row.get::<_, Option<String>>(i)?.unwrap_or_default())
+    };
+    let path = download_path(config, &text(1)?, &text(0)?, &text(7)?);
     Ok(Arc::new(Book {
-        collection: query.params.collection.clone(),
-        title: row.get(0)?,
-        authors: row.get(1)?,
-        series: row.get(2)?,
-        year: row.get(3)?,
-        language: row.get(4)?,
-        publisher: row.get(5)?,
-        sizeinbytes: row.get(6)?,
-        format: row.get(7)?,
-        ipfs_cid: row.get(8)?,
+        collection: Collection::from_table(&text(10)?),
+        title: text(0)?,
+        authors: text(1)?,
+        series: text(2)?,
+        year: text(3)?,
+        language: text(4)?,
+        publisher: text(5)?,
+        sizeinbytes: row.get::<_, Option<i64>>(6)?.unwrap_or_default(),
+        format: text(7)?,
+        ipfs_cid: text(8)?,
+        md5: text(9)?.to_lowercase(),
         duplicates: RwLock::new(1),
         download_status: RwLock::new("?".to_string()),
+        download_progress: RwLock::new(None),
         download_path: path,
     }))
 }
@@ -245,7 +1128,7 @@ fn sanitize(filename: &str) -> String {
         .collect::<String>()
 }
 
-fn download_path(config: &Config, authors: &String, title: &String, format: &String) -> PathBuf {
+fn download_path(config: &Config, authors: &str, title: &str, format: &str) -> PathBuf {
     let author_subfolder = config.get::<bool>("authorSubfolder").unwrap_or_default();
     let filename = match author_subfolder {
         true => PathBuf::from(sanitize(authors))
@@ -255,6 +1138,1500 @@ fn download_path(config: &Config, authors: &String, title: &String, format: &Str
             .with_extension(format),
     };
     let download_path = config.get::<String>("downloadPath").unwrap_or_default();
-    let path = &Path::new(&download_path).join(filename);
-    return path.to_owned();
+    Path::new(&download_path).join(filename)
+}
+
+// Create and fill the FTS5 index for a `_mini` table if it isn't there yet.
+// Returns false if the table itself doesn't exist.
+fn ensure_fts(
+    connection: &rusqlite::Connection,
+    alias: &str,
+    table: &str,
+) -> rusqlite::Result<bool> {
+    let exists = |name: &str| -> rusqlite::Result<bool> {
+        connection.query_row(
+            &format!("SELECT count(*) FROM {alias}.sqlite_master WHERE name = ?1"),
+            [name],
+            |row| row.get::<_, i64>(0).map(|n| n > 0),
+        )
+    };
+    if !exists(table)? {
+        return Ok(false);
+    }
+    if exists(&format!("{table}_fts"))? {
+        return Ok(true);
+    }
+    log::info!("Building full-text index for {}.{}", alias, table);
+    // external content table: the index refers back to the rows of `table`
+    // instead of keeping its own copy of the text
+    connection.execute_batch(&format!(
+        "
+        CREATE VIRTUAL TABLE {alias}.{table}_fts USING fts5(
+            Title, Author, Series, Publisher,
+            content='{table}', content_rowid='rowid'
+        );
+        INSERT INTO {alias}.{table}_fts({table}_fts) VALUES('rebuild');
+        "
+    ))?;
+    log::info!("Built full-text index for {}.{}", alias, table);
+    Ok(true)
+}
+
+fn build_query(params: Params, sources: &[(String, Collection, Schema)], fts: bool) -> Query {
+    let fts_match = fts_match(&params);
+    let use_fts =
+        fts && params.mode != SearchMode::Like && !params.transliterate && !fts_match.is_empty();
+    let several = sources.len() > 1;
+    let mut selects = Vec::new();
+    let mut args: Vec<(String, Value)> = Vec::new();
+    for (alias, collection, schema) in sources {
+        let (select, select_args) =
+            build_select(&params, alias, collection, *schema, use_fts, several);
+        selects.push(select);
+        // every select binds the same values to the same names
+        for arg in select_args {
+            if !args.iter().any(|(name, _)| name == &arg.0) {
+                args.push(arg);
+            }
+        }
+    }
+    if use_fts {
+        args.push((":match".to_string(), Value::Text(fts_match)));
+        if params.mode == SearchMode::Fuzzy {
+            let words = searched_words(&params).join(" ");
+            args.push((":words".to_string(), Value::Text(words)));
+        }
+    }
+    let limit = match params.page_size {
+        0 => -1, // no limit
+        n => n as i64,
+    };
+    args.push((":limit".to_string(), Value::Integer(limit)));
+    let stmt = match selects.len() {
+        1 => selects.remove(0),
+        // each select is already sorted and cut to a page, so the first page
+        // of the whole is in there somewhere
+        _ => {
+            let keys = (0..order_keys(&params, "", "", use_fts, several).len())
+                .map(|i| format!("k{i}"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            format!(
+                "SELECT * FROM ({}) ORDER BY {keys} LIMIT :limit",
+                selects.join(") UNION ALL SELECT * FROM (")
+            )
+        }
+    };
+    Query {
+        id: 0,
+        stmt,
+        args,
+        params,
+        sources: sources.to_vec(),
+        fts,
+    }
+}
+
+// The search over one collection in one file, with the named arguments it
+// uses apart from :match and :limit
+fn build_select(
+    params: &Params,
+    alias: &str,
+    collection: &Collection,
+    schema: Schema,
+    use_fts: bool,
+    several: bool,
+) -> (String, Vec<(String, Value)>) {
+    let table = collection.table();
+    let mut args = Vec::new();
+    let mut wheres = Vec::new();
+    let (columns, from) = if use_fts {
+        // https://www.sqlite.org/fts5.html
+        wheres.push(format!("{table}_mini_fts MATCH :match"));
+        (
+            "f.title, f.author, f.series, f.year, f.language, f.publisher, f.filesize, f.extension, f.ipfs_cid",
+            format!(
+                "{alias}.{table}_mini_fts JOIN {alias}.{table}_mini f ON f.rowid = {table}_mini_fts.rowid"
+            ),
+        )
+    } else if schema.is_mini() {
+        (
+            "f.title, f.author, f.series, f.year, f.language, f.publisher, f.filesize, f.extension, f.ipfs_cid",
+            format!("{alias}.{table}_mini f"),
+        )
+    } else {
+        (
+            "f.title, f.author, f.series, f.year, f.language, f.publisher, f.filesize, f.extension, fh.ipfs_cid",
+            format!("{alias}.{table} f join {alias}.{table}_hashes as fh on LOWER(f.md5) = fh.md5"),
+        )
+    };
+    let filters = [
+        (Field::Language, ":language", &params.language),
+        (Field::Format, ":format", &params.format),
+        (Field::Title, ":title", &params.title),
+        (Field::Authors, ":authors", &params.authors),
+        (Field::Series, ":series", &params.series),
+    ];
+    // both sides folded, so accents, case and script don't matter
+    let transliterate = params.transliterate as i32;
+    let folded = |column: &str| format!("fold({column}, {transliterate})");
+    for (field, name, text) in filters {
+        let column = sql_column(field);
+        if text.is_empty() {
+            continue;
+        }
+        if params.regex.contains(&field) {
+            // matched against the text as it is, ignoring only case
+            wheres.push(format!("{column} REGEXP {name}"));
+            args.push((name.to_string(), Value::Text(text.clone())));
+        } else if use_fts && fts_columns(Some(field)).is_some() {
+            // already part of the MATCH
+            continue;
+        } else {
+            wheres.push(format!("{} LIKE '%'||{name}||'%'", folded(column)));
+            let text = fold(text, params.transliterate);
+            args.push((name.to_string(), Value::Text(text)));
+        }
+    }
+    for (i, term) in params.terms.iter().enumerate() {
+        if use_fts && !term.negated && fts_columns(term.field).is_some() {
+            // already part of the MATCH
+            continue;
+        }
+        let name = format!(":t{i}");
+        let text = fold(&term.text, params.transliterate);
+        args.push((name.clone(), Value::Text(text)));
+        let columns = match term.field {
+            None => vec!["f.title", "f.author", "f.series"],
+            Some(field) => vec![sql_column(field)],
+        };
+        let likes: Vec<String> = columns
+            .iter()
+            .map(|c| format!("{} LIKE '%'||{name}||'%'", folded(c)))
+            .collect();
+        let clause = format!("({})", likes.join(" OR "));
+        wheres.push(match term.negated {
+            // a NULL column doesn't contain the excluded text either
+            true => format!("NOT ifnull({clause}, 0)"),
+            false => clause,
+        });
+    }
+
+    if let Some(author) = &params.author {
+        wheres.push("f.author = :author".to_string());
+        args.push((":author".to_string(), Value::Text(author.clone())));
+    }
+
+    // topics only exist in non-fiction, so they don't apply to a search of both
+    if params.collection == Collection::NonFiction && !params.topics.is_empty() {
+        // ids come from the topics table, not from the user, so they can go
+        // straight into the SQL
+        let ids: Vec<String> = params.topics.iter().map(i64::to_string).collect();
+        wheres.push(format!("CAST(f.topic AS INTEGER) IN ({})", ids.join(", ")));
+    }
+
+    let ranges = [
+        ("parse_year(f.year) >=", ":min_year", params.min_year),
+        ("parse_year(f.year) <=", ":max_year", params.max_year),
+        ("f.filesize >=", ":min_size", params.min_size),
+        ("f.filesize <=", ":max_size", params.max_size),
+    ];
+    for (test, name, bound) in ranges {
+        if let Some(bound) = bound {
+            wheres.push(format!("{test} {name}"));
+            args.push((name.to_string(), Value::Integer(bound)));
+        }
+    }
+
+    let order = order_keys(params, alias, table, use_fts, several);
+    if let Some(Cursor(after)) = &params.after {
+        let keys: Vec<String> = (0..after.len()).map(|i| format!(":k{i}")).collect();
+        wheres.push(after_cursor(&order, &keys));
+        args.extend(keys.into_iter().zip(after.iter().cloned()));
+    }
+    if wheres.is_empty() {
+        wheres.push("1".to_string());
+    }
+    // named so that a search of several collections can sort the union
+    let keys: Vec<String> = order
+        .iter()
+        .enumerate()
+        .map(|(i, key)| format!("{key} AS k{i}"))
+        .collect();
+    let select = format!(
+        "
+        SELECT {columns}, {}, '{table}', {}
+        FROM {from}
+        WHERE {}
+        ORDER BY {}
+        LIMIT :limit
+        ",
+        schema.md5_column(),
+        keys.join(", "),
+        wheres.join(" AND "),
+        order.join(", "),
+    );
+    (select, args)
+}
+
+// The ORDER BY for a search of `table`, which is also the page cursor.
+// rowid last so that the order is total and a page boundary never splits
+// or repeats rows; when searching several tables the rowids can clash, so
+// the table goes before it.
+fn order_keys(
+    params: &Params,
+    alias: &str,
+    table: &str,
+    use_fts: bool,
+    several: bool,
+) -> Vec<String> {
+    let mut order = match params.mode {
+        // weights are per column: title, author, series, publisher
+        SearchMode::Relevance if use_fts => {
+            vec![format!("bm25({table}_mini_fts, 10.0, 5.0, 2.0, 1.0)")]
+        }
+        // the most alike first
+        SearchMode::Fuzzy if use_fts => vec![
+            "-similarity(ifnull(f.title, '') || ' ' || ifnull(f.author, ''), :words)".to_string(),
+        ],
+        // the bare columns, so the (Author, Title, ...) index gives the order
+        _ => vec![
+            "f.author".to_string(),
+            "f.title".to_string(),
+            "f.filesize".to_string(),
+        ],
+    };
+    if several {
+        order.push(format!("'{alias}.{table}'"));
+    }
+    order.push("f.rowid".to_string());
+    order
+}
+
+// The rows after the cursor `keys` in the order `order`. A row value
+// comparison is NULL as soon as a key is, so this spells it out a key at a
+// time, with NULL sorting first as it does in ORDER BY.
+fn after_cursor(order: &[String], keys: &[String]) -> String {
+    let mut terms = Vec::new();
+    for (i, (key, value)) in order.iter().zip(keys).enumerate() {
+        let mut tests: Vec<String> = order
+            .iter()
+            .zip(keys)
+            .take(i)
+            .map(|(key, value)| format!("{key} IS {value}"))
+            .collect();
+        tests.push(format!(
+            "({key} > {value} OR ({value} IS NULL AND {key} IS NOT NULL))"
+        ));
+        terms.push(format!("({})", tests.join(" AND ")));
+    }
+    format!("({})", terms.join(" OR "))
+}
+
+// every row of a query, without streaming or paging
+fn load_books(connection: &rusqlite::Connection, query: &Query) -> rusqlite::Result<Vec<BookRef>> {
+    let mut stmt = connection.prepare(&query.stmt)?;
+    let args: Vec<(&str, &dyn ToSql)> = query
+        .args
+        .iter()
+        .map(|(name, value)| (name.as_str(), value as &dyn ToSql))
+        .collect();
+    let config = load_settings();
+    let mut rows = stmt.query(args.as_slice())?;
+    let mut books = Vec::new();
+    while let Some(row) = rows.next()? {
+        books.push(row_to_book(&config, row)?);
+    }
+    Ok(books)
+}
+
+// authors listed at once; typing more of the name narrows it down
+const AUTHORS_SHOWN: usize = 1000;
+
+// Distinct authors starting with `prefix` and how many books each has.
+// The range on the first letter lets SQLite walk the Author index instead
+// of the whole table; LIKE then matches the rest of the prefix on the folded
+// name, since LIKE by itself only ignores the case of ASCII letters.
+fn load_authors(
+    connection: &rusqlite::Connection,
+    tables: &[String],
+    prefix: &str,
+) -> rusqlite::Result<Vec<(String, i64)>> {
+    let prefix = prefix.trim_start();
+    let mut wheres = vec!["Author <> ''".to_string()];
+    let mut args: Vec<(String, Value)> = Vec::new();
+    if let Some(first) = prefix.chars().next() {
+        let mut ranges = Vec::new();
+        let mut cases: Vec<char> = first.to_lowercase().chain(first.to_uppercase()).collect();
+        cases.dedup();
+        for (i, c) in cases.into_iter().enumerate() {
+            let next = match char::from_u32(c as u32 + 1) {
+                Some(next) => next,
+                None => continue,
+            };
+            ranges.push(format!("(Author >= :from{i} AND Author < :to{i})"));
+            args.push((format!(":from{i}"), Value::Text(c.to_string())));
+            args.push((format!(":to{i}"), Value::Text(next.to_string())));
+        }
+        if !ranges.is_empty() {
+            wheres.push(format!("({})", ranges.join(" OR ")));
+        }
+        wheres.push("fold(Author, 0) LIKE :prefix || '%' ESCAPE '\\'".to_string());
+        let escaped = fold(prefix, false)
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_");
+        args.push((":prefix".to_string(), Value::Text(escaped)));
+    }
+    let wheres = wheres.join(" AND ");
+    let counts: Vec<String> = tables
+        .iter()
+        .map(|table| {
+            format!("SELECT Author, count(*) AS books FROM {table} WHERE {wheres} GROUP BY Author")
+        })
+        .collect();
+    let mut stmt = connection.prepare(&format!(
+        "
+        SELECT Author, sum(books) FROM ({})
+        GROUP BY Author
+        ORDER BY Author
+        LIMIT {AUTHORS_SHOWN}
+        ",
+        counts.join(" UNION ALL ")
+    ))?;
+    let args: Vec<(&str, &dyn ToSql)> = args
+        .iter()
+        .map(|(name, value)| (name.as_str(), value as &dyn ToSql))
+        .collect();
+    let authors = stmt.query_map(args.as_slice(), |row| Ok((row.get(0)?, row.get(1)?)))?;
+    authors.collect()
+}
+
+// the table with a collection's book details
+fn books_table(collection: &Collection, schema: Schema) -> String {
+    match schema {
+        Schema::Mini | Schema::MiniWithoutMd5 => format!("{}_mini", collection.table()),
+        Schema::Full => collection.table().to_string(),
+    }
+}
+
+// values kept for each group, so that totals across files stay close
+const STATS_KEPT: usize = 100;
+
+// The statistics for the tables of one catalog file: the ones kept in its
+// `catalog_stats` table, or if there are none or `refresh`, counted again.
+// Counting takes a while, so `true` says these are new and worth keeping.
+fn load_stats(
+    connection: &rusqlite::Connection,
+    alias: &str,
+    tables: &[(Collection, String)],
+    refresh: bool,
+) -> rusqlite::Result<(Stats, bool)> {
+    let cached: bool = connection.query_row(
+        &format!("SELECT count(*) > 0 FROM {alias}.sqlite_master WHERE name = 'catalog_stats'"),
+        [],
+        |row| row.get(0),
+    )?;
+    if refresh || !cached {
+        log::info!("Counting catalog statistics for {}", alias);
+        return Ok((count_stats(connection, alias, tables)?, true));
+    }
+    let mut stmt = connection.prepare(&format!(
+        "SELECT grp, value, count FROM {alias}.catalog_stats"
+    ))?;
+    let rows = stmt
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok((Stats { rows }, false))
+}
+
+// Keep counted statistics in the file's `catalog_stats` table, replacing
+// what was there. Anything that goes wrong undoes the lot.
+fn save_stats(
+    connection: &rusqlite::Connection,
+    alias: &str,
+    stats: &Stats,
+) -> rusqlite::Result<()> {
+    // rolled back when dropped without committing
+    let transaction = connection.unchecked_transaction()?;
+    transaction.execute_batch(&format!(
+        "
+        DROP TABLE IF EXISTS {alias}.catalog_stats;
+        CREATE TABLE {alias}.catalog_stats (grp TEXT, value TEXT, count INTEGER);
+        "
+    ))?;
+    let mut insert = transaction.prepare(&format!(
+        "INSERT INTO {alias}.catalog_stats (grp, value, count) VALUES (?1, ?2, ?3)"
+    ))?;
+    for (group, value, count) in &stats.rows {
+        insert.execute(rusqlite::params![group, value, count])?;
+    }
+    drop(insert);
+    transaction.commit()
+}
+
+fn count_stats(
+    connection: &rusqlite::Connection,
+    alias: &str,
+    tables: &[(Collection, String)],
+) -> rusqlite::Result<Stats> {
+    let mut rows = vec![];
+    let mut collect = |sql: &str, params: &[&dyn rusqlite::ToSql]| -> rusqlite::Result<()> {
+        let mut stmt = connection.prepare(sql)?;
+        let found = stmt.query_map(params, |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+        for row in found {
+            rows.push(row?);
+        }
+        Ok(())
+    };
+    for (collection, table) in tables {
+        collect(
+            &format!(
+                "
+                SELECT 'Collection', ?1, count(*) FROM {alias}.{table}
+                UNION ALL
+                SELECT 'Size', ?1, ifnull(sum(Filesize), 0) FROM {alias}.{table}
+                "
+            ),
+            &[&collection.name()],
+        )?;
+    }
+    let books = tables
+        .iter()
+        .map(|(_, table)| {
+            format!("SELECT Language, Extension, Year, Author, Publisher FROM {alias}.{table}")
+        })
+        .collect::<Vec<_>>()
+        .join(" UNION ALL ");
+    let groups = [
+        ("Language", "trim(ifnull(Language, ''))"),
+        ("Format", "lower(trim(ifnull(Extension, '')))"),
+        ("Decade", "ifnull((parse_year(Year) / 10 * 10) || 's', '')"),
+        ("Author", "trim(ifnull(Author, ''))"),
+        ("Publisher", "trim(ifnull(Publisher, ''))"),
+    ];
+    for (group, value) in groups {
+        collect(
+            &format!(
+                "
+                SELECT '{group}', {value} AS v, count(*) AS n FROM ({books})
+                WHERE v <> ''
+                GROUP BY v
+                ORDER BY n DESC
+                LIMIT {STATS_KEPT}
+                "
+            ),
+            &[],
+        )?;
+    }
+    Ok(Stats { rows })
+}
+
+// The English topic names with how many books are filed under each
+fn load_topics(
+    connection: &rusqlite::Connection,
+    tables: &[String],
+) -> rusqlite::Result<Vec<Topic>> {
+    let books: Vec<String> = tables
+        .iter()
+        .map(|table| format!("SELECT Topic FROM {table}"))
+        .collect();
+    // `topics` is the first one found in any of the attached files
+    let mut stmt = connection.prepare(&format!(
+        "
+        SELECT t.topic_id, t.topic_id_hlm, t.topic_descr, ifnull(c.books, 0)
+        FROM topics t
+        LEFT JOIN (
+            SELECT CAST(Topic AS INTEGER) AS topic, count(*) AS books
+            FROM ({})
+            GROUP BY 1
+        ) c ON c.topic = CAST(t.topic_id AS INTEGER)
+        WHERE t.lang = 'en'
+        ORDER BY t.topic_id
+        ",
+        books.join(" UNION ALL ")
+    ))?;
+    let topics = stmt.query_map([], |row| {
+        let id: i64 = row.get(0)?;
+        Ok(Topic {
+            id,
+            parent: row.get::<_, Option<i64>>(1)?.unwrap_or(id),
+            name: row.get::<_, Option<String>>(2)?.unwrap_or_default(),
+            count: row.get::<_, i64>(3)? as usize,
+        })
+    })?;
+    topics.collect()
+}
+
+// Turn the text filters into an FTS5 query where every word has to appear as
+// a prefix in its column, e.g. `Author:"pratch"* AND Title:"guards"*`.
+// Empty if there's nothing for the index to match on.
+fn fts_match(params: &Params) -> String {
+    let columns = [
+        (Field::Title, "Title", &params.title),
+        (Field::Authors, "Author", &params.authors),
+        (Field::Series, "Series", &params.series),
+    ];
+    let mut terms = Vec::new();
+    for (field, column, text) in columns {
+        if params.regex.contains(&field) {
+            continue;
+        }
+        for word in text.split_whitespace() {
+            terms.extend(fts_word(column, word, &params.similar));
+        }
+    }
+    for term in params.terms.iter().filter(|t| !t.negated) {
+        if let Some(column) = fts_columns(term.field) {
+            if term.phrase {
+                terms.extend(fts_phrase(column, &term.text));
+            } else {
+                for word in term.text.split_whitespace() {
+                    terms.extend(fts_word(column, word, &params.similar));
+                }
+            }
+        }
+    }
+    terms.join(" AND ")
+}
+
+// A word as a prefix, or any of the words like it that fuzzy mode found:
+// `Author:("pratchet"* OR "pratchett")`
+fn fts_word(column: &str, word: &str, similar: &[(String, Vec<String>)]) -> Option<String> {
+    let phrase = fts_phrase(column, word)?;
+    match similar.iter().find(|(w, _)| w == word) {
+        Some((_, others)) if !others.is_empty() => {
+            let quoted: Vec<String> = others
+                .iter()
+                .map(|other| format!("\"{}\"", other.replace('"', "\"\"")))
+                .collect();
+            let prefix = &phrase[column.len() + 1..];
+            Some(format!("{column}:({prefix} OR {})", quoted.join(" OR ")))
+        }
+        _ => Some(phrase),
+    }
+}
+
+fn fts_phrase(column: &str, text: &str) -> Option<String> {
+    // the tokenizer drops punctuation, so a word without any letters
+    // or digits would be an empty phrase
+    if !text.chars().any(char::is_alphanumeric) {
+        return None;
+    }
+    Some(format!("{column}:\"{}\"*", text.replace('"', "\"\"")))
+}
+
+// the indexed columns a term can be matched against, if any
+fn fts_columns(field: Option<Field>) -> Option<&'static str> {
+    match field {
+        None => Some("{Title Author Series}"),
+        Some(Field::Title) => Some("Title"),
+        Some(Field::Authors) => Some("Author"),
+        Some(Field::Series) => Some("Series"),
+        Some(_) => None,
+    }
+}
+
+fn sql_column(field: Field) -> &'static str {
+    match field {
+        Field::Title => "f.title",
+        Field::Authors => "f.author",
+        Field::Series => "f.series",
+        Field::Language => "f.language",
+        Field::Format => "f.extension",
+        Field::Publisher => "f.publisher",
+        Field::Year => "f.year",
+        Field::Size => "f.filesize",
+    }
+}
+
+// SQL functions the queries rely on
+fn register_functions(connection: &rusqlite::Connection) -> rusqlite::Result<()> {
+    let flags = || FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC;
+    connection.create_scalar_function("parse_year", 1, flags(), |ctx| {
+        Ok(match ctx.get_raw(0) {
+            ValueRef::Integer(year) => Some(year),
+            ValueRef::Text(text) => parse_year(&String::from_utf8_lossy(text)),
+            _ => None,
+        })
+    })?;
+    // fold(text, transliterate): text as the filters compare it
+    connection.create_scalar_function("fold", 2, flags(), |ctx| {
+        let transliterate = ctx.get::<bool>(1)?;
+        Ok(match ctx.get_raw(0) {
+            ValueRef::Text(text) | ValueRef::Blob(text) => {
+                Some(fold(&String::from_utf8_lossy(text), transliterate))
+            }
+            // numbers as LIKE would see them, for `size:` and `year:` terms
+            ValueRef::Integer(n) => Some(n.to_string()),
+            ValueRef::Real(n) => Some(n.to_string()),
+            ValueRef::Null => None,
+        })
+    })?;
+    // `text REGEXP pattern` calls regexp(pattern, text)
+    connection.create_scalar_function("regexp", 2, flags(), |ctx| {
+        // compiled the first time and kept with the statement
+        let regex: Arc<Regex> = ctx.get_or_create_aux(0, |pattern| -> Result<_, BoxError> {
+            Ok(compile_pattern(pattern.as_str()?)?)
+        })?;
+        Ok(match ctx.get_raw(1) {
+            ValueRef::Text(text) => regex.is_match(&String::from_utf8_lossy(text)),
+            ValueRef::Integer(n) => regex.is_match(&n.to_string()),
+            ValueRef::Real(n) => regex.is_match(&n.to_string()),
+            ValueRef::Null | ValueRef::Blob(_) => false,
+        })
+    })?;
+    // similarity(text, words): how close the words in the text are to the
+    // searched words, from 0 to 1
+    connection.create_scalar_function("similarity", 2, flags(), |ctx| {
+        let text = ctx.get::<Option<String>>(0)?.unwrap_or_default();
+        let words = ctx.get::<Option<String>>(1)?.unwrap_or_default();
+        Ok(fuzzy::text_similarity(&text, &words))
+    })
+}
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+// Patterns from the regex boxes ignore case like the other filters do
+pub fn compile_pattern(pattern: &str) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(pattern).case_insensitive(true).build()
+}
+
+// The regex boxes whose patterns don't compile and why, to show instead of
+// searching
+pub fn invalid_patterns(params: &Params) -> Vec<(Field, String)> {
+    let boxes = [
+        (Field::Title, &params.title),
+        (Field::Authors, &params.authors),
+        (Field::Series, &params.series),
+        (Field::Language, &params.language),
+        (Field::Format, &params.format),
+    ];
+    boxes
+        .into_iter()
+        .filter(|(field, _)| params.regex.contains(field))
+        .filter_map(|(field, text)| Some((field, compile_pattern(text).err()?.to_string())))
+        .collect()
+}
+
+// A series field split into its name and volume number, so "Discworld 8",
+// "Discworld; 8", "Discworld #8" and "Discworld, book 8" are all volume 8 of
+// "Discworld".
+pub fn parse_series(text: &str) -> (&str, Option<f64>) {
+    let text = text.trim();
+    let (name, last) = match text.rsplit_once(|c: char| c.is_whitespace() || c == '#') {
+        Some(split) => split,
+        None => return (text, None),
+    };
+    let volume = match last.trim_start_matches('#').parse::<f64>() {
+        Ok(volume) if volume.is_finite() => volume,
+        _ => return (text, None),
+    };
+    let separator = |c: char| c.is_whitespace() || ",;#-".contains(c);
+    let mut name = name.trim_end_matches(separator);
+    for word in ["book", "vol.", "vol", "volume", "part", "no."] {
+        let cut = name.len().saturating_sub(word.len());
+        // a word of its own, not the end of "Facebook"
+        if cut > 0
+            && name.is_char_boundary(cut)
+            && name[cut..].eq_ignore_ascii_case(word)
+            && name[..cut].ends_with(separator)
+        {
+            name = name[..cut].trim_end_matches(separator);
+            break;
+        }
+    }
+    (name, Some(volume))
+}
+
+// The year in a free-form year field: "2001-2003" is 2001, "c1999" is 1999.
+pub fn parse_year(text: &str) -> Option<i64> {
+    let mut digits = 0;
+    for (i, c) in text.char_indices() {
+        if c.is_ascii_digit() {
+            digits += 1;
+            continue;
+        }
+        if digits == 4 {
+            return text[i - 4..i].parse().ok();
+        }
+        digits = 0;
+    }
+    match digits {
+        4 => text[text.len() - 4..].parse().ok(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // an in-memory catalog with an empty `fiction_mini` table, then `rows`
+    fn mini_catalog(rows: &str) -> rusqlite::Connection {
+        let connection = rusqlite::Connection::open_in_memory().unwrap();
+        register_functions(&connection).unwrap();
+        connection
+            .execute_batch(
+                "CREATE TABLE fiction_mini (ipfs_cid, Title, Author, Series, Language, Year, Publisher, Extension, Filesize, Locator)",
+            )
+            .unwrap();
+        connection.execute_batch(rows).unwrap();
+        connection
+    }
+
+    fn fiction() -> Vec<(String, Collection, Schema)> {
+        vec![(
+            "main".to_string(),
+            Collection::Fiction,
+            Schema::MiniWithoutMd5,
+        )]
+    }
+
+    #[test]
+    fn test_fts_match() {
+        let params = Params {
+            title: "guards \"guards\" !".to_string(),
+            authors: "pratchett".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            fts_match(&params),
+            r#"Title:"guards"* AND Title:"""guards"""* AND Author:"pratchett"*"#
+        );
+        assert_eq!(fts_match(&Params::default()), "");
+    }
+
+    #[test]
+    fn test_fulltext_query() {
+        let connection = mini_catalog(
+            "
+            INSERT INTO fiction_mini VALUES ('a', 'Guards! Guards!', 'Pratchett, Terry', 'Discworld 8', 'English', '1989', 'Gollancz', 'epub', 100, '');
+            INSERT INTO fiction_mini VALUES ('b', 'Mort', 'Pratchett, Terry', 'Discworld 4', 'English', '1987', 'Gollancz', 'epub', 100, '');
+            ",
+        );
+        assert!(ensure_fts(&connection, "main", "fiction_mini").unwrap());
+        assert!(!ensure_fts(&connection, "main", "non_fiction_mini").unwrap());
+
+        let params = Params {
+            title: "guard".to_string(),
+            authors: "pratch".to_string(),
+            mode: SearchMode::Relevance,
+            ..Default::default()
+        };
+        let source = Source::detect(&connection, "main", "").unwrap();
+        assert_eq!(source.fiction, Some(Schema::MiniWithoutMd5));
+        assert_eq!(source.non_fiction, None);
+        let query = build_query(params, &fiction(), true);
+        assert!(query.stmt.contains("MATCH"));
+        let books = run(&connection, &query).0;
+        assert_eq!(books.len(), 1);
+        assert_eq!(books[0].title, "Guards! Guards!");
+
+        // relevance order pages on the bm25 score
+        let mut params = Params {
+            authors: "pratchett".to_string(),
+            mode: SearchMode::Relevance,
+            page_size: 1,
+            ..Default::default()
+        };
+        let (first, more) = run(&connection, &build_query(params.clone(), &fiction(), true));
+        params.after = more;
+        let (second, _) = run(&connection, &build_query(params, &fiction(), true));
+        assert_eq!(first.len() + second.len(), 2);
+        assert_ne!(first[0].title, second[0].title);
+    }
+
+    #[test]
+    fn test_folding() {
+        let connection = mini_catalog(
+            "
+            INSERT INTO fiction_mini VALUES ('a', 'Cien años de soledad', 'García Márquez, Gabriel', '', 'Español', '1967', NULL, 'epub', 100, '');
+            INSERT INTO fiction_mini VALUES ('b', 'Идиот', 'Достоевский, Фёдор', '', 'Русский', '1869', NULL, 'FB2', 100, '');
+            ",
+        );
+        let titles = |params: Params| -> Vec<String> {
+            let query = build_query(params, &fiction(), false);
+            run(&connection, &query)
+                .0
+                .iter()
+                .map(|b| b.title.clone())
+                .collect()
+        };
+        let params = Params {
+            authors: "GARCIA marquez".to_string(),
+            language: "espanol".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(titles(params), vec!["Cien años de soledad"]);
+        let params = Params {
+            authors: "фёдор".to_string(),
+            format: "fb2".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(titles(params), vec!["Идиот"]);
+
+        // Latin for Cyrillic only when asked for
+        let mut params = Params {
+            authors: "dostoevsk".to_string(),
+            terms: vec![Term {
+                field: Some(Field::Title),
+                text: "SOLEDAD".to_string(),
+                negated: true,
+                phrase: false,
+            }],
+            ..Default::default()
+        };
+        assert!(titles(params.clone()).is_empty());
+        params.transliterate = true;
+        assert_eq!(titles(params.clone()), vec!["Идиот"]);
+        // the index can't do it, so it isn't used
+        params.mode = SearchMode::FullText;
+        assert!(!build_query(params, &fiction(), true).stmt.contains("MATCH"));
+    }
+
+    #[test]
+    fn test_regex() {
+        let connection = mini_catalog(
+            "
+            INSERT INTO fiction_mini VALUES ('a', 'Mort', 'Pratchett, Terry', NULL, 'English', '1987', NULL, 'epub', 100, '');
+            INSERT INTO fiction_mini VALUES ('b', 'Mortal Engines', 'Reeve, Philip', NULL, 'English', '2001', NULL, 'epub', 100, '');
+            INSERT INTO fiction_mini VALUES ('c', 'Eric', 'Pratchett, Terry', 'Discworld 9', 'English', '1990', NULL, 'epub', 100, '');
+            ",
+        );
+        ensure_fts(&connection, "main", "fiction_mini").unwrap();
+        let titles = |params: Params| -> Vec<String> {
+            let query = build_query(params, &fiction(), true);
+            run(&connection, &query)
+                .0
+                .iter()
+                .map(|b| b.title.clone())
+                .collect()
+        };
+        let mut params = Params {
+            title: "^m.rt$".to_string(),
+            regex: vec![Field::Title],
+            ..Default::default()
+        };
+        assert_eq!(titles(params.clone()), vec!["Mort"]);
+        // the pattern stays out of the full-text match
+        params.mode = SearchMode::FullText;
+        params.authors = "pratchett".to_string();
+        assert_eq!(titles(params.clone()), vec!["Mort"]);
+        // a missing series doesn't match anything
+        params.series = "\\d".to_string();
+        params.regex.push(Field::Series);
+        assert!(titles(params.clone()).is_empty());
+
+        assert!(invalid_patterns(&params).is_empty());
+        params.series = "(".to_string();
+        let invalid = invalid_patterns(&params);
+        assert_eq!(invalid.len(), 1);
+        assert_eq!(invalid[0].0, Field::Series);
+        // not a regex box, so not a pattern
+        params.regex.clear();
+        assert!(invalid_patterns(&params).is_empty());
+    }
+
+    #[test]
+    fn test_fuzzy() {
+        let connection = mini_catalog(
+            "
+            INSERT INTO fiction_mini VALUES ('a', 'The Idiot', 'Dostoevsky, Fyodor', '', 'English', '1869', NULL, 'epub', 100, '');
+            INSERT INTO fiction_mini VALUES ('b', 'Mort', 'Pratchett, Terry', 'Discworld 4', 'English', '1987', NULL, 'epub', 100, '');
+            INSERT INTO fiction_mini VALUES ('c', 'Eric', 'Pratchett, Terry', 'Discworld 9', 'English', '1990', NULL, 'epub', 100, '');
+            INSERT INTO fiction_mini VALUES ('d', 'Mortal Engines', 'Reeve, Philip', '', 'English', '2001', NULL, 'epub', 100, '');
+            ",
+        );
+        ensure_fts(&connection, "main", "fiction_mini").unwrap();
+        fuzzy::ensure_trigrams(&connection, "main", "fiction_mini").unwrap();
+        let tables = vec!["main.fiction_mini".to_string()];
+
+        let params = Params {
+            authors: "dostoyevsky".to_string(),
+            mode: SearchMode::Fuzzy,
+            ..Default::default()
+        };
+        // without the other spellings nothing matches
+        let query = build_query(params, &fiction(), true);
+        assert!(run(&connection, &query).0.is_empty());
+        let query = fuzzy_query(&connection, &tables, query);
+        assert_eq!(
+            query.params.similar,
+            vec![("dostoyevsky".to_string(), vec!["dostoevsky".to_string()])]
+        );
+        let books = run(&connection, &query).0;
+        assert_eq!(books.len(), 1);
+        assert_eq!(books[0].title, "The Idiot");
+
+        // the closest matches come first
+        let params = Params {
+            title: "mort".to_string(),
+            mode: SearchMode::Fuzzy,
+            ..Default::default()
+        };
+        let query = fuzzy_query(&connection, &tables, build_query(params, &fiction(), true));
+        let titles: Vec<String> = run(&connection, &query)
+            .0
+            .iter()
+            .map(|b| b.title.clone())
+            .collect();
+        assert_eq!(titles, vec!["Mort", "Mortal Engines"]);
+
+        // did you mean
+        let params = Params {
+            title: "mort".to_string(),
+            authors: "pratchet, tery".to_string(),
+            ..Default::default()
+        };
+        let suggestions = suggest(&connection, &tables, &params).unwrap();
+        assert_eq!(suggestions.len(), 1);
+        assert_eq!(suggestions[0].title, "mort");
+        assert_eq!(suggestions[0].authors, "pratchett terry");
+        let params = Params {
+            authors: "pratchett".to_string(),
+            ..Default::default()
+        };
+        assert!(suggest(&connection, &tables, &params).unwrap().is_empty());
+    }
+
+    fn run(connection: &rusqlite::Connection, query: &Query) -> (Vec<BookRef>, Option<Cursor>) {
+        let (send, recv) = unbounded();
+        let produced = AtomicUsize::new(0);
+        start_query(
+            connection,
+            query,
+            &send,
+            &AtomicU64::new(query.id),
+            &produced,
+        )
+        .unwrap();
+        let mut books = Vec::new();
+        for (_, response) in recv.try_iter() {
+            match response {
+                Response::Books(b) => books.extend(b),
+                Response::PageEnd(more) => return (books, more),
+                Response::Error(e) => panic!("{}", e),
+                _ => {}
+            }
+        }
+        panic!("no end of page");
+    }
+
+    #[test]
+    fn test_terms() {
+        let connection = mini_catalog(
+            "
+            INSERT INTO fiction_mini VALUES ('a', 'Guards! Guards!', 'Pratchett, Terry', 'Discworld', 'English', '1989', NULL, 'epub', 100, '');
+            INSERT INTO fiction_mini VALUES ('b', 'Guards! Guards!', 'Pratchett, Terry', 'Discworld', 'English', '1991', NULL, 'pdf', 100, '');
+            INSERT INTO fiction_mini VALUES ('c', 'Night Watch', 'Pratchett, Terry', 'Discworld', 'English', '2002', NULL, 'epub', 100, '');
+            ",
+        );
+        ensure_fts(&connection, "main", "fiction_mini").unwrap();
+        for mode in [SearchMode::Like, SearchMode::FullText] {
+            let mut params = Params {
+                mode,
+                ..Default::default()
+            };
+            crate::search::apply(
+                crate::search::parse(r#"discworld -format:pdf year:<1990 "guards! g""#).unwrap(),
+                &mut params,
+            );
+            let query = build_query(params, &fiction(), true);
+            let books = run(&connection, &query).0;
+            let cids: Vec<&str> = books.iter().map(|b| b.ipfs_cid.as_str()).collect();
+            assert_eq!(cids, vec!["a"], "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn test_topics() {
+        let connection = rusqlite::Connection::open_in_memory().unwrap();
+        register_functions(&connection).unwrap();
+        connection
+            .execute_batch(
+                "
+                CREATE TABLE non_fiction_mini (ipfs_cid, Title, Author, Series, Language, Year, Publisher, Extension, Filesize, Locator, Topic TEXT);
+                INSERT INTO non_fiction_mini VALUES ('a', 'Jet Engines', 'Rolls', NULL, 'English', NULL, NULL, 'pdf', 100, '', '211');
+                INSERT INTO non_fiction_mini VALUES ('b', 'Machines', 'Smith', NULL, 'English', NULL, NULL, 'pdf', 100, '', '210');
+                INSERT INTO non_fiction_mini VALUES ('c', 'Poems', 'Jones', NULL, 'English', NULL, NULL, 'pdf', 100, '', '57');
+                CREATE TABLE topics (topic_descr TEXT, lang TEXT, kolxoz_code TEXT, topic_id INTEGER, topic_id_hlm INTEGER);
+                INSERT INTO topics VALUES ('Технология', 'ru', '', 210, 210);
+                INSERT INTO topics VALUES ('Technology', 'en', '', 210, 210);
+                INSERT INTO topics VALUES ('Technology\\Aerospace', 'en', '', 211, 210);
+                ",
+            )
+            .unwrap();
+        assert!(Source::detect(&connection, "main", "").unwrap().topics);
+        let topics = load_topics(&connection, &["main.non_fiction_mini".to_string()]).unwrap();
+        assert_eq!(
+            topics,
+            vec![
+                Topic {
+                    id: 210,
+                    parent: 210,
+                    name: "Technology".to_string(),
+                    count: 1
+                },
+                Topic {
+                    id: 211,
+                    parent: 210,
+                    name: "Technology\\Aerospace".to_string(),
+                    count: 1
+                },
+            ]
+        );
+
+        let params = Params {
+            collection: Collection::NonFiction,
+            topics: vec![210, 211],
+            ..Default::default()
+        };
+        let query = build_query(
+            params,
+            &[(
+                "main".to_string(),
+                Collection::NonFiction,
+                Schema::MiniWithoutMd5,
+            )],
+            false,
+        );
+        let books = run(&connection, &query).0;
+        let cids: Vec<&str> = books.iter().map(|b| b.ipfs_cid.as_str()).collect();
+        assert_eq!(cids, vec!["a", "b"]);
+    }
+
+    #[test]
+    fn test_all_collections() {
+        let connection = mini_catalog(
+            "
+            INSERT INTO fiction_mini VALUES ('a', 'Mort', 'Pratchett, Terry', NULL, 'English', NULL, NULL, 'epub', 100, '');
+            INSERT INTO fiction_mini VALUES ('b', 'Eric', 'Pratchett, Terry', NULL, 'English', NULL, NULL, 'epub', 100, '');
+            ATTACH DATABASE ':memory:' AS extra;
+            CREATE TABLE extra.non_fiction_mini (ipfs_cid, Title, Author, Series, Language, Year, Publisher, Extension, Filesize, Locator, Topic);
+            INSERT INTO extra.non_fiction_mini VALUES ('c', 'Mort', 'Pratchett, Terry', NULL, 'English', NULL, NULL, 'epub', 100, '', NULL);
+            INSERT INTO extra.non_fiction_mini VALUES ('d', 'Art', 'Anon', NULL, 'English', NULL, NULL, 'pdf', 100, '', NULL);
+            ",
+        );
+        ensure_fts(&connection, "main", "fiction_mini").unwrap();
+        ensure_fts(&connection, "extra", "non_fiction_mini").unwrap();
+        let sources = &[
+            (
+                "main".to_string(),
+                Collection::Fiction,
+                Schema::MiniWithoutMd5,
+            ),
+            (
+                "extra".to_string(),
+                Collection::NonFiction,
+                Schema::MiniWithoutMd5,
+            ),
+        ];
+        for (mode, fts) in [(SearchMode::Like, false), (SearchMode::Relevance, true)] {
+            // one row per page, to cross from one collection to the other
+            let mut params = Params {
+                collection: Collection::All,
+                authors: "pratchett".to_string(),
+                mode,
+                page_size: 1,
+                ..Default::default()
+            };
+            let mut books = Vec::new();
+            loop {
+                let (page, more) = run(&connection, &build_query(params.clone(), sources, fts));
+                books.extend(page);
+                match more {
+                    Some(cursor) => params.after = Some(cursor),
+                    None => break,
+                }
+            }
+            let mut found: Vec<(&str, Collection)> = books
+                .iter()
+                .map(|b| (b.ipfs_cid.as_str(), b.collection.clone()))
+                .collect();
+            if mode == SearchMode::Like {
+                assert_eq!(found[0].0, "b", "sorted by title across collections");
+            }
+            found.sort_by_key(|(cid, _)| *cid);
+            assert_eq!(
+                found,
+                vec![
+                    ("a", Collection::Fiction),
+                    ("b", Collection::Fiction),
+                    ("c", Collection::NonFiction)
+                ],
+                "{:?}",
+                mode
+            );
+        }
+    }
+
+    #[test]
+    fn test_catalog_open() {
+        let path = std::env::temp_dir().join("rlgdesktop-test-catalog.sqlite");
+        let _ = std::fs::remove_file(&path);
+        rusqlite::Connection::open(&path)
+            .unwrap()
+            .execute_batch(
+                "
+                CREATE TABLE non_fiction_mini (ipfs_cid, Title);
+                CREATE TABLE topics (topic_id);
+                CREATE TABLE fiction_mini (ipfs_cid, Title, Author, Series, Language, Year, Publisher, Extension, Filesize, Locator, MD5);
+                INSERT INTO fiction_mini VALUES ('a', 'Mort', 'Pratchett, Terry', NULL, 'English', NULL, NULL, 'epub', 100, '', 'ABC123');
+                ",
+            )
+            .unwrap();
+        let path = path.to_string_lossy().to_string();
+        let connection = rusqlite::Connection::open_in_memory().unwrap();
+        register_functions(&connection).unwrap();
+        let catalog = Catalog::open(&connection, &["missing.sqlite".to_string(), path.clone()]);
+        assert_eq!(catalog.warnings, vec!["missing.sqlite does not exist"]);
+        assert_eq!(catalog.sources.len(), 1);
+        assert_eq!(catalog.sources[0].path, path);
+        assert_eq!(
+            catalog.collections(),
+            vec![Collection::Fiction, Collection::NonFiction]
+        );
+        assert!(catalog.topics());
+        assert_eq!(
+            catalog.tables(&Collection::All),
+            vec![
+                ("db1".to_string(), Collection::Fiction, Schema::Mini),
+                (
+                    "db1".to_string(),
+                    Collection::NonFiction,
+                    Schema::MiniWithoutMd5
+                )
+            ]
+        );
+        // the md5 comes through in lower case where the catalog has it
+        let query = build_query(
+            Params::default(),
+            &catalog.tables(&Collection::Fiction),
+            false,
+        );
+        let books = load_books(&connection, &query).unwrap();
+        assert_eq!(books[0].md5, "abc123");
+        drop(connection);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_readers() {
+        let path = std::env::temp_dir().join("rlgdesktop-test-readers.sqlite");
+        let _ = std::fs::remove_file(&path);
+        mini_catalog(
+            "
+            INSERT INTO fiction_mini VALUES ('a', 'Mort', 'Pratchett, Terry', NULL, 'English', '1987', NULL, 'epub', 100, '');
+            INSERT INTO fiction_mini VALUES ('b', 'Eric', 'Pratchett, Terry', NULL, 'English', '1990', NULL, 'epub', 100, '');
+            ",
+        )
+        .execute("VACUUM INTO ?1", [path.to_string_lossy()])
+        .unwrap();
+        let paths = vec![path.to_string_lossy().to_string()];
+        let (search, _) = connect(&paths).unwrap();
+        let (reader, _) = connect(&paths).unwrap();
+        reader.pragma_update(None, "query_only", true).unwrap();
+        assert!(reader
+            .execute_batch("DELETE FROM db0.fiction_mini")
+            .is_err());
+
+        let authors = Answers::new();
+        let stats = Answers::new();
+        let (cache_send, cache_receive) = unbounded();
+        let replies = Replies {
+            topics: unbounded().0,
+            stats: stats.send.clone(),
+            authors: authors.send.clone(),
+            bibliography: unbounded().0,
+            cache: cache_send,
+            repaint: None,
+        };
+        let (request_send, request_receive) = unbounded();
+        let reader = thread::spawn(move || serve_requests(&reader, &request_receive, &replies));
+
+        // stopping the search leaves the reader alone
+        search.get_interrupt_handle().interrupt();
+        for prefix in ["x", "pr"] {
+            let request = Request::Authors {
+                id: authors.next_id(),
+                tables: vec!["db0.fiction_mini".to_string()],
+                prefix: prefix.to_string(),
+            };
+            request_send.send(request).unwrap();
+        }
+        let files = vec![(
+            "db0".to_string(),
+            vec![(Collection::Fiction, "fiction_mini".to_string())],
+        )];
+        request_send
+            .send(Request::Stats {
+                id: stats.next_id(),
+                files,
+                refresh: false,
+            })
+            .unwrap();
+        drop(request_send);
+        reader.join().unwrap();
+
+        // only the answer to the latest request
+        assert_eq!(
+            authors.get(),
+            Some(Ok(vec![("Pratchett, Terry".to_string(), 2)]))
+        );
+        assert_eq!(authors.get(), None);
+        let stats = stats.get().unwrap().unwrap();
+        assert_eq!(stats.group("Collection"), vec![("Fiction", 2)]);
+        // the new counts go to the writer rather than into the file
+        let (alias, cached) = cache_receive.try_recv().unwrap();
+        assert_eq!(alias, "db0");
+        assert_eq!(cached.group("Collection"), vec![("Fiction", 2)]);
+        let tables: i64 = search
+            .query_row(
+                "SELECT count(*) FROM db0.sqlite_master WHERE name = 'catalog_stats'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(tables, 0);
+        drop(search);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_get_result() {
+        let (response_send, response_receive) = bounded(RESPONSE_QUEUE);
+        let db = DB {
+            query_send: unbounded().0,
+            response_receive,
+            interrupt: None,
+            generation: Arc::new(AtomicU64::new(0)),
+            processing: Arc::new(AtomicBool::new(false)),
+            produced: Arc::new(AtomicUsize::new(0)),
+            fts_tables: Arc::new(RwLock::new(Vec::new())),
+            catalog: Catalog::default(),
+            request_send: unbounded().0,
+            topic_answers: Answers::new(),
+            stats_answers: Answers::new(),
+            author_answers: Answers::new(),
+            bibliography_answers: Answers::new(),
+        };
+        let error = |text: &str| Response::Error(text.to_string());
+        response_send.send((0, error("old"))).unwrap();
+        db.cancel();
+        response_send.send((0, error("older still"))).unwrap();
+        response_send.send((1, error("current"))).unwrap();
+        // the answers to the cancelled query are dropped on the way
+        match db.get_result() {
+            Some(Response::Error(text)) => assert_eq!(text, "current"),
+            _ => panic!("expected the current response"),
+        }
+        assert!(db.get_result().is_none());
+        assert!(response_send.is_empty());
+    }
+
+    #[test]
+    fn test_stats() {
+        let connection = mini_catalog(
+            "
+            INSERT INTO fiction_mini VALUES ('a', 'Mort', 'Pratchett, Terry', NULL, 'English', '1987', NULL, 'epub', 100, '');
+            INSERT INTO fiction_mini VALUES ('b', 'Eric', 'Pratchett, Terry', NULL, 'English', 'c1990', NULL, 'EPUB', 200, '');
+            CREATE TABLE non_fiction_mini (ipfs_cid, Title, Author, Series, Language, Year, Publisher, Extension, Filesize, Locator, Topic);
+            INSERT INTO non_fiction_mini VALUES ('c', 'Art', 'Anon', NULL, 'French', NULL, 'Gallimard', 'pdf', 300, '', NULL);
+            ",
+        );
+        let tables = vec![
+            (Collection::Fiction, "fiction_mini".to_string()),
+            (Collection::NonFiction, "non_fiction_mini".to_string()),
+        ];
+        let (stats, counted) = load_stats(&connection, "main", &tables, false).unwrap();
+        assert!(counted);
+        assert_eq!(
+            stats.group("Collection"),
+            vec![("Fiction", 2), ("Nonfiction", 1)]
+        );
+        assert_eq!(
+            stats.group("Size"),
+            vec![("Fiction", 300), ("Nonfiction", 300)]
+        );
+        assert_eq!(stats.group("Format"), vec![("epub", 2), ("pdf", 1)]);
+        assert_eq!(stats.group("Decade"), vec![("1980s", 1), ("1990s", 1)]);
+        assert_eq!(stats.group("Publisher"), vec![("Gallimard", 1)]);
+
+        // cached until asked to count again
+        save_stats(&connection, "main", &stats).unwrap();
+        connection
+            .execute_batch("DELETE FROM non_fiction_mini")
+            .unwrap();
+        let (stats, counted) = load_stats(&connection, "main", &tables, false).unwrap();
+        assert!(!counted);
+        assert_eq!(stats.group("Author").len(), 2);
+        let (mut stats, counted) = load_stats(&connection, "main", &tables, true).unwrap();
+        assert!(counted);
+        assert_eq!(stats.group("Author"), vec![("Pratchett, Terry", 2)]);
+
+        // a failed save leaves no transaction open and the old cache in place
+        let bad = Stats {
+            rows: vec![("Author".to_string(), "x".to_string(), 1)],
+        };
+        assert!(save_stats(&connection, "nosuch", &bad).is_err());
+        assert!(connection.is_autocommit());
+        let (cached, _) = load_stats(&connection, "main", &tables, false).unwrap();
+        assert_eq!(cached.group("Author").len(), 2);
+
+        stats.merge(stats.clone());
+        assert_eq!(stats.group("Author"), vec![("Pratchett, Terry", 4)]);
+    }
+
+    #[test]
+    fn test_authors() {
+        let connection = mini_catalog(
+            "
+            CREATE INDEX idx_fiction_mini_Search ON fiction_mini (Author, Title, Series, Language, Extension);
+            INSERT INTO fiction_mini VALUES ('a', 'Mort', 'Pratchett, Terry', 'Discworld 4', 'English', NULL, NULL, 'epub', 100, '');
+            INSERT INTO fiction_mini VALUES ('b', 'Eric', 'Pratchett, Terry', 'Discworld 9', 'English', NULL, NULL, 'epub', 100, '');
+            INSERT INTO fiction_mini VALUES ('c', 'Nation', 'pratt, anon', NULL, 'English', NULL, NULL, 'epub', 100, '');
+            INSERT INTO fiction_mini VALUES ('d', 'Good Omens', 'Pratchett, Terry; Gaiman, Neil', NULL, 'English', NULL, NULL, 'epub', 100, '');
+            INSERT INTO fiction_mini VALUES ('e', 'Coraline', 'Gaiman, Neil', NULL, 'English', NULL, NULL, 'epub', 100, '');
+            INSERT INTO fiction_mini VALUES ('f', '100%', 'Pr%', NULL, 'English', NULL, NULL, 'epub', 100, '');
+            INSERT INTO fiction_mini VALUES ('g', 'Идиот', 'Достоевский, Фёдор', NULL, 'Russian', NULL, NULL, 'fb2', 100, '');
+            INSERT INTO fiction_mini VALUES ('h', 'Nana', 'Émile Zola', NULL, 'French', NULL, NULL, 'epub', 100, '');
+            ",
+        );
+        let tables = ["main.fiction_mini".to_string()];
+        let authors = load_authors(&connection, &tables, "PRATCH").unwrap();
+        assert_eq!(
+            authors,
+            vec![
+                ("Pratchett, Terry".to_string(), 2),
+                ("Pratchett, Terry; Gaiman, Neil".to_string(), 1)
+            ]
+        );
+        assert_eq!(load_authors(&connection, &tables, "pr").unwrap().len(), 4);
+        assert_eq!(load_authors(&connection, &tables, "pr%").unwrap().len(), 1);
+        assert_eq!(load_authors(&connection, &tables, "").unwrap().len(), 7);
+        // past the first letter, case and accents beyond ASCII are ignored too
+        assert_eq!(
+            load_authors(&connection, &tables, "дОСТОЕВ").unwrap(),
+            vec![("Достоевский, Фёдор".to_string(), 1)]
+        );
+        assert_eq!(
+            load_authors(&connection, &tables, "émile").unwrap(),
+            vec![("Émile Zola".to_string(), 1)]
+        );
+
+        let params = Params {
+            author: Some("Pratchett, Terry".to_string()),
+            ..Default::default()
+        };
+        let books = load_books(&connection, &build_query(params, &fiction(), false)).unwrap();
+        let titles: Vec<&str> = books.iter().map(|b| b.title.as_str()).collect();
+        assert_eq!(titles, vec!["Eric", "Mort"]);
+    }
+
+    #[test]
+    fn test_parse_series() {
+        assert_eq!(parse_series("Discworld 8"), ("Discworld", Some(8.0)));
+        assert_eq!(parse_series("Discworld; 8"), ("Discworld", Some(8.0)));
+        assert_eq!(parse_series("Discworld #8.5"), ("Discworld", Some(8.5)));
+        assert_eq!(parse_series("Discworld, Book 8"), ("Discworld", Some(8.0)));
+        assert_eq!(parse_series("Facebook 2"), ("Facebook", Some(2.0)));
+        assert_eq!(parse_series("Catch-22"), ("Catch-22", None));
+        assert_eq!(parse_series(" Discworld "), ("Discworld", None));
+        assert_eq!(parse_series(""), ("", None));
+    }
+
+    #[test]
+    fn test_parse_year() {
+        assert_eq!(parse_year("2001-2003"), Some(2001));
+        assert_eq!(parse_year("c1999"), Some(1999));
+        assert_eq!(parse_year("1999."), Some(1999));
+        assert_eq!(parse_year("12345"), None);
+        assert_eq!(parse_year(""), None);
+    }
+
+    #[test]
+    fn test_pages() {
+        let connection = mini_catalog(
+            "
+            INSERT INTO fiction_mini VALUES ('a', 'Mort', 'Pratchett, Terry', NULL, 'English', NULL, NULL, 'epub', 100, '');
+            INSERT INTO fiction_mini VALUES ('b', 'Mort', 'Pratchett, Terry', NULL, 'English', NULL, NULL, 'epub', 100, '');
+            INSERT INTO fiction_mini VALUES ('c', 'Eric', 'Pratchett, Terry', NULL, 'English', NULL, NULL, 'epub', 100, '');
+            ",
+        );
+        let mut params = Params {
+            page_size: 2,
+            ..Default::default()
+        };
+        let (first, more) = run(&connection, &build_query(params.clone(), &fiction(), false));
+        assert_eq!(first.len(), 2);
+        assert!(more.is_some());
+        params.after = more;
+        let (second, more) = run(&connection, &build_query(params, &fiction(), false));
+        assert_eq!(second.len(), 1);
+        assert!(more.is_none());
+        let cids: Vec<&str> = first
+            .iter()
+            .chain(second.iter())
+            .map(|b| b.ipfs_cid.as_str())
+            .collect();
+        assert_eq!(cids, vec!["c", "a", "b"]);
+    }
+
+    #[test]
+    fn test_pages_with_nulls() {
+        let connection = mini_catalog(
+            "
+            INSERT INTO fiction_mini VALUES ('a', 'Beowulf', NULL, NULL, 'English', NULL, NULL, 'epub', NULL, '');
+            INSERT INTO fiction_mini VALUES ('b', NULL, NULL, NULL, 'English', NULL, NULL, 'epub', 100, '');
+            INSERT INTO fiction_mini VALUES ('c', 'Beowulf', NULL, NULL, 'English', NULL, NULL, 'epub', 100, '');
+            INSERT INTO fiction_mini VALUES ('d', 'Mort', 'Pratchett, Terry', NULL, 'English', NULL, NULL, 'epub', 100, '');
+            ",
+        );
+        let mut params = Params {
+            page_size: 1,
+            ..Default::default()
+        };
+        let mut cids = Vec::new();
+        loop {
+            let (page, more) = run(&connection, &build_query(params.clone(), &fiction(), false));
+            cids.extend(page.iter().map(|b| b.ipfs_cid.clone()));
+            match more {
+                Some(cursor) => params.after = Some(cursor),
+                None => break,
+            }
+        }
+        assert_eq!(cids, vec!["b", "a", "c", "d"]);
+
+        // the search index gives the order, with or without a cursor
+        connection
+            .execute_batch("CREATE INDEX idx_fiction_mini_Search ON fiction_mini (Author, Title, Series, Language, Extension)")
+            .unwrap();
+        for after in [None, params.after.clone()] {
+            let query = build_query(
+                Params {
+                    after,
+                    ..params.clone()
+                },
+                &fiction(),
+                false,
+            );
+            let mut stmt = connection
+                .prepare(&format!("EXPLAIN QUERY PLAN {}", query.stmt))
+                .unwrap();
+            let args: Vec<(&str, &dyn ToSql)> = query
+                .args
+                .iter()
+                .map(|(name, value)| (name.as_str(), value as &dyn ToSql))
+                .collect();
+            let plan: Vec<String> = stmt
+                .query_map(args.as_slice(), |row| row.get(3))
+                .unwrap()
+                .map(|detail| detail.unwrap())
+                .collect();
+            assert!(plan
+                .iter()
+                .any(|detail| detail.contains("idx_fiction_mini_Search")));
+        }
+    }
 }
@@ -2,18 +2,85 @@ use bytes::Bytes;
 use config::Config;
 use crossbeam::channel::{unbounded, Receiver, Sender};
 use fstrings::{f, format_args_f};
-use std::{error, thread, time::Duration};
-use tokio::task::JoinSet;
+use std::{
+    collections::HashMap,
+    error, fmt,
+    fs::File,
+    io::{BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant},
+};
+use tokio::task::{JoinSet, LocalSet};
 
-use crate::{config::load_settings, db::BookRef};
+use crate::{config::load_settings, db::BookRef, stats::format_bytes, verify};
 
 #[derive(Debug, Default, Clone)]
 pub struct Status {
     pub completed: u64,
     pub errors: u64,
     pub description: String,
+    // downloads in progress, and how far they've got between them
+    pub active: usize,
+    pub progress: Option<Progress>,
 }
 
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Progress {
+    pub received: u64,
+    // Content-Length, or the catalog's file size if the host doesn't say
+    pub total: Option<u64>,
+    // bytes per second since the download started
+    pub throughput: f64,
+}
+
+impl Progress {
+    pub fn fraction(&self) -> Option<f32> {
+        match self.total {
+            Some(total) if total > 0 => Some((self.received as f64 / total as f64).min(1.0) as f32),
+            _ => None,
+        }
+    }
+
+    pub fn eta(&self) -> Option<Duration> {
+        let left = self.total?.saturating_sub(self.received);
+        match self.throughput > 0.0 {
+            true => Some(Duration::from_secs_f64(left as f64 / self.throughput)),
+            false => None,
+        }
+    }
+}
+
+// "1.5 MB of 4.0 MB, 512.0 KB/s, 5s left"
+impl fmt::Display for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", format_bytes(self.received as i64))?;
+        if let Some(total) = self.total {
+            write!(f, " of {}", format_bytes(total as i64))?;
+        }
+        write!(f, ", {}/s", format_bytes(self.throughput as i64))?;
+        if let Some(eta) = self.eta() {
+            write!(f, ", {}s left", eta.as_secs())?;
+        }
+        Ok(())
+    }
+}
+
+// how often progress is passed on while a file downloads
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+// give up on a host that sends nothing for this long
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+// how many times to pick up a download again after it stops part way
+const RESUME_ATTEMPTS: u32 = 3;
+// bytes already on disk asked for again when resuming, to check the host is
+// sending the same file
+const OVERLAP: u64 = 4096;
+
+// books downloaded at once unless `concurrentDownloads` says otherwise
+const CONCURRENT: usize = 3;
+
 pub struct Download {
     pub queue: Sender<BookRef>,
     pub status: Receiver<Status>,
@@ -24,25 +91,15 @@ impl Download {
         let (queue, recv) = unbounded::<BookRef>();
         let (status_send, status_recv) = unbounded::<Status>();
         let config = load_settings();
-        let mut status = Status::default();
-
-        // run downloads off the UI thread
-        thread::spawn(move || loop {
-            if let Ok(book) = recv.recv() {
-                if let Err(e) = start_download(&book, &mut status, &status_send, &config) {
-                    log::error!("Error downloading book {}: {}", book.title, e);
-                    if let Ok(mut s) = book.download_status.write() {
-                        *s = format!("Error: {}", e);
-                    }
-                    status.description = format!("Error: {}", e);
-                    status.errors += 1;
-                    if let Err(e) = status_send.send(status.clone()) {
-                        log::error!("Error sending error: {}", e);
-                    }
-                }
-            } else {
-                break;
-            }
+
+        // run downloads off the UI thread, several at once on one runtime
+        thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+            let shared = Rc::new(Shared::new(config, status_send));
+            LocalSet::new().block_on(&runtime, run(recv, shared));
         });
 
         Self {
@@ -51,25 +108,217 @@ impl Download {
         }
     }
 
+    // the latest status, skipping any progress the ui didn't get to
     pub fn get_status(&self) -> Option<Status> {
-        self.status.try_recv().ok()
+        self.status.try_iter().last()
     }
 }
 
-fn start_download(
-    book: &BookRef,
-    status: &mut Status,
-    status_send: &Sender<Status>,
-    config: &Config,
-) -> Result<(), Box<dyn error::Error>> {
-    status.description = format!("Downloading {}", book.title);
+// What the downloads in progress share
+struct Shared {
+    config: Config,
+    status: Mutex<Status>,
+    status_send: Sender<Status>,
+    // each download in progress, by CID
+    progress: Mutex<HashMap<String, Progress>>,
+    // one connection pool for each gateway
+    clients: Mutex<HashMap<String, reqwest::Client>>,
+    limit: Option<Limit>,
+    // the file each running download writes, and other books queued for
+    // the same file while it was running
+    paths: Mutex<HashMap<PathBuf, Vec<BookRef>>>,
+}
+
+impl Shared {
+    fn new(config: Config, status_send: Sender<Status>) -> Self {
+        // KB/s across every download
+        let limit = match config.get::<u64>("downloadLimit").unwrap_or_default() {
+            0 => None,
+            kb => Some(Limit::new(kb * 1024)),
+        };
+        Self {
+            config,
+            status: Mutex::new(Status::default()),
+            status_send,
+            progress: Mutex::new(HashMap::new()),
+            clients: Mutex::new(HashMap::new()),
+            limit,
+            paths: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Claim the file `book` is saved as, or if another download has it,
+    // wait for that one instead of writing the same file twice
+    fn claim(&self, book: &BookRef) -> bool {
+        let mut paths = self.paths.lock().unwrap_or_else(|e| e.into_inner());
+        match paths.get_mut(&book.download_path) {
+            Some(waiting) => {
+                waiting.push(book.clone());
+                false
+            }
+            None => {
+                paths.insert(book.download_path.clone(), vec![]);
+                true
+            }
+        }
+    }
+
+    // let go of the file, and tell the books waiting for it how it went
+    fn release(&self, book: &BookRef) {
+        let mut paths = self.paths.lock().unwrap_or_else(|e| e.into_inner());
+        let status = match book.download_status.read() {
+            Ok(status) => status.clone(),
+            Err(_) => return,
+        };
+        for waiting in paths.remove(&book.download_path).unwrap_or_default() {
+            if let Ok(mut s) = waiting.download_status.write() {
+                *s = status.clone();
+            }
+        }
+    }
+
+    // change the status and pass it on to the ui
+    fn update(&self, change: impl FnOnce(&mut Status)) {
+        if let Ok(mut status) = self.status.lock() {
+            change(&mut status);
+            if let Err(e) = self.status_send.send(status.clone()) {
+                log::error!("Error sending status: {}", e);
+            }
+        }
+    }
+
+    // record how far a book has got, or that it's stopped, and pass on the
+    // progress of all of them together
+    fn set_progress(&self, book: &BookRef, progress: Option<Progress>) {
+        if let Ok(mut p) = book.download_progress.write() {
+            *p = progress.clone();
+        }
+        let all = match self.progress.lock() {
+            Ok(mut all) => {
+                match progress {
+                    Some(progress) => all.insert(book.ipfs_cid.clone(), progress),
+                    None => all.remove(&book.ipfs_cid),
+                };
+                combine(all.values())
+            }
+            Err(_) => return,
+        };
+        self.update(|status| status.progress = all);
+    }
+
+    // the gateway's client, so connections to it are kept and reused
+    fn client(&self, host: &str) -> reqwest::Result<reqwest::Client> {
+        let mut clients = self.clients.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(client) = clients.get(host) {
+            return Ok(client.clone());
+        }
+        // no overall timeout, big books take a while; `save` notices a stall
+        let client = reqwest::Client::builder()
+            .connect_timeout(Duration::from_secs(30))
+            .build()?;
+        clients.insert(host.to_string(), client.clone());
+        Ok(client)
+    }
+}
+
+// several downloads' progress as one
+fn combine<'a>(all: impl Iterator<Item = &'a Progress>) -> Option<Progress> {
+    let mut combined: Option<Progress> = None;
+    for progress in all {
+        let sum = combined.get_or_insert(Progress {
+            total: Some(0),
+            ..Default::default()
+        });
+        sum.received += progress.received;
+        sum.total = sum.total.zip(progress.total).map(|(a, b)| a + b);
+        sum.throughput += progress.throughput;
+    }
+    combined
+}
+
+// A cap on how fast all the downloads together read, in bytes a second
+struct Limit {
+    rate: f64,
+    // when what's been read so far would have been read at the cap
+    next: Mutex<Instant>,
+}
+
+impl Limit {
+    fn new(rate: u64) -> Self {
+        Self {
+            rate: rate as f64,
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    // wait out the time `bytes` take at the cap
+    async fn take(&self, bytes: usize) {
+        let wait = match self.next.lock() {
+            Ok(mut next) => {
+                let now = Instant::now();
+                *next = (*next).max(now) + Duration::from_secs_f64(bytes as f64 / self.rate);
+                *next - now
+            }
+            Err(_) => return,
+        };
+        tokio::time::sleep(wait).await;
+    }
+}
+
+// Take books off the queue, starting each as soon as fewer than
+// `concurrentDownloads` are running
+async fn run(queue: Receiver<BookRef>, shared: Rc<Shared>) {
+    let concurrent = shared
+        .config
+        .get::<usize>("concurrentDownloads")
+        .unwrap_or(CONCURRENT)
+        .max(1);
+    let mut downloads = JoinSet::new();
+    loop {
+        // books waiting for a turn stay queued
+        while downloads.len() >= concurrent {
+            downloads.join_next().await;
+        }
+        let queue = queue.clone();
+        let book = match tokio::task::spawn_blocking(move || queue.recv()).await {
+            Ok(Ok(book)) => book,
+            _ => break,
+        };
+        if !shared.claim(&book) {
+            log::info!("{} is already downloading", book.download_path.display());
+            if let Ok(mut s) = book.download_status.write() {
+                *s = String::from("Downloading")
+            }
+            continue;
+        }
+        let shared = shared.clone();
+        downloads.spawn_local(async move {
+            shared.update(|status| status.active += 1);
+            if let Err(e) = start_download(&book, &shared).await {
+                log::error!("Error downloading book {}: {}", book.title, e);
+                if let Ok(mut s) = book.download_status.write() {
+                    *s = format!("Error: {}", e);
+                }
+                shared.update(|status| {
+                    status.description = format!("Error downloading {}: {}", book.title, e);
+                    status.errors += 1;
+                });
+            }
+            shared.set_progress(&book, None);
+            shared.release(&book);
+            shared.update(|status| status.active -= 1);
+        });
+    }
+}
+
+async fn start_download(book: &BookRef, shared: &Shared) -> Result<(), Box<dyn error::Error>> {
+    shared.update(|status| status.description = format!("Downloading {}", book.title));
     if let Ok(mut s) = book.download_status.write() {
         *s = String::from("Downloading")
     }
     let path = &book.download_path;
     if path.exists() {
-        status.description = f!("{book.title} already exists");
-        status_send.send(status.clone())?;
+        shared.update(|status| status.description = f!("{book.title} already exists"));
         log::info!("{} already exists at {}", book.title, path.display());
         if let Ok(mut s) = book.download_status.write() {
             *s = String::from("Done")
@@ -77,78 +326,356 @@ fn start_download(
         return Ok(());
     }
 
-    let hosts: Vec<String> = config
+    let hosts: Vec<String> = shared
+        .config
         .get::<String>("url_ipfs_hosts")?
         .split_ascii_whitespace()
         .map(|s| s.to_string())
         .collect();
 
-    let runtime = tokio::runtime::Builder::new_current_thread()
-        .enable_all()
-        .build()
-        .unwrap();
-
-    let maybe_bytes = runtime.block_on(download_race(hosts, book));
-    match maybe_bytes {
-        Ok(bytes) => {
-            let kb = bytes.len() / 1024;
-            status.description = f!("Writing {book.title} to disk, {kb} KiB");
-            status_send.send(status.clone())?;
-            std::fs::create_dir_all(path.parent().unwrap())?;
-            // write and flush the file to disk
-            std::fs::write(path, &bytes)?;
-            log::info!("Wrote {kb} KiB {}", path.display());
-            // assert that the file size is correct
-            let metadata = std::fs::metadata(path)?;
-            let size = metadata.len() as usize;
-            if size != bytes.len() {
-                return Err(format!(
-                    "File size mismatch: expected {} bytes, got {} bytes",
-                    bytes.len(),
-                    size
-                )
-                .into());
-            } else {
-                log::info!("File size matches: {} bytes", size);
-            }
-            status.completed += 1;
-            status.description = f!("Downloaded {book.title}");
-            if let Ok(mut s) = book.download_status.write() {
-                *s = String::from("Done")
+    // written beside the finished file and renamed once it's all there, so
+    // a half-downloaded book never looks done. It's kept if the download
+    // fails, to be picked up from where it stopped next time.
+    std::fs::create_dir_all(path.parent().unwrap())?;
+    let part = part_path(path);
+    let mut attempt = 0;
+    // hosts that sent the wrong file, not to be asked again
+    let mut rejected: Vec<String> = Vec::new();
+    let result = loop {
+        let offset = partial_size(&part, book);
+        let rejections = rejected.len();
+        let hosts: Vec<String> = hosts
+            .iter()
+            .filter(|host| !rejected.contains(host))
+            .cloned()
+            .collect();
+        let result = match download_race(hosts, book, offset, shared).await {
+            Ok(started) => save(started, book, &part, offset, shared, &mut rejected).await,
+            Err(e) => Err(e.into()),
+        };
+        match result {
+            // go again as long as each attempt gets somewhere
+            Err(e)
+                if attempt < RESUME_ATTEMPTS
+                    && (partial_size(&part, book) != offset || rejected.len() > rejections) =>
+            {
+                attempt += 1;
+                log::warn!("Resuming {} after error: {}", book.title, e);
             }
-            status_send.send(status.clone())?;
+            result => break result,
         }
-        Err(e) => {
-            status.description = format!("Error downloading {}: {}", book.title, e);
-            if let Ok(mut s) = book.download_status.write() {
-                *s = format!("Error: {}", e);
+    };
+    shared.set_progress(book, None);
+    let received = match result {
+        Ok(received) => received,
+        Err(e) => return Err(e),
+    };
+
+    // a host can send the wrong file as happily as the right one; hashing
+    // is slow, so it's done off the runtime to keep other downloads going
+    shared.update(|status| status.description = f!("Checking {book.title}"));
+    let checked = {
+        let (book, part, config) = (book.clone(), part.clone(), shared.config.clone());
+        tokio::task::spawn_blocking(move || check(&book, &part, &config)).await?
+    };
+    if let Err(e) = checked {
+        return Err(quarantine(&part, path, &shared.config, e));
+    }
+    std::fs::rename(&part, path)?;
+    let kb = received / 1024;
+    log::info!("Wrote {kb} KiB {}", path.display());
+    if let Ok(mut s) = book.download_status.write() {
+        *s = String::from("Done")
+    }
+    shared.update(|status| {
+        status.completed += 1;
+        status.description = f!("Downloaded {book.title}");
+    });
+    Ok(())
+}
+
+// Compare a finished download with the catalog's md5, and its CID too if
+// `verifyCid` is on. A book added to IPFS with other settings than the
+// defaults fails the CID check even if it's right, which is why it's opt-in.
+fn check(book: &BookRef, part: &Path, config: &Config) -> Result<(), String> {
+    if !book.md5.is_empty() {
+        let md5 = verify::md5_file(part).map_err(|e| e.to_string())?;
+        if md5 != book.md5 {
+            return Err(f!("md5 is {md5}, expected {book.md5}"));
+        }
+    }
+    if config.get::<bool>("verifyCid").unwrap_or_default() {
+        match verify::file_cid(part, &book.ipfs_cid) {
+            Ok(cid) if cid != book.ipfs_cid => {
+                return Err(f!("CID is {cid}, expected {book.ipfs_cid}"));
             }
-            status.errors += 1;
-            status_send.send(status.clone())?;
+            Ok(_) => {}
+            // nothing to say the file is wrong
+            Err(e) => log::warn!("Can't check the CID of {}: {}", book.title, e),
         }
     }
     Ok(())
 }
 
-async fn download_race(hosts: Vec<String>, book: &BookRef) -> Result<Bytes, String> {
+// Move a download that failed its checks out of the way, into `quarantine`
+// under `downloadPath`, so it's neither mistaken for the book nor resumed.
+fn quarantine(part: &Path, path: &Path, config: &Config, reason: String) -> Box<dyn error::Error> {
+    let folder =
+        PathBuf::from(config.get::<String>("downloadPath").unwrap_or_default()).join("quarantine");
+    let moved = std::fs::create_dir_all(&folder).and_then(|_| {
+        let target = folder.join(path.file_name().unwrap_or_default());
+        std::fs::rename(part, &target).map(|_| target)
+    });
+    match moved {
+        Ok(target) => {
+            log::error!("{}, moved to {}", reason, target.display());
+            f!("{reason}, moved to quarantine").into()
+        }
+        Err(e) => {
+            log::error!("{}, and can't quarantine it: {}", reason, e);
+            let _ = std::fs::remove_file(part);
+            f!("{reason}, deleted").into()
+        }
+    }
+}
+
+// where a book is written while it downloads
+fn part_path(path: &Path) -> PathBuf {
+    let mut part = path.as_os_str().to_owned();
+    part.push(".part");
+    PathBuf::from(part)
+}
+
+// The size of what's already been downloaded of a book, or 0 to start again.
+// A partial file bigger than the whole book can't be the book, so it goes.
+fn partial_size(part: &Path, book: &BookRef) -> u64 {
+    let size = match std::fs::metadata(part) {
+        Ok(metadata) => metadata.len(),
+        Err(_) => return 0,
+    };
+    if book.sizeinbytes > 0 && size > book.sizeinbytes as u64 {
+        log::warn!("Discarding {}, it's bigger than the book", part.display());
+        let _ = std::fs::remove_file(part);
+        return 0;
+    }
+    size
+}
+
+// where a resumed download asks to start from, a little before the end of
+// what's on disk so the two can be compared
+fn resume_from(offset: u64) -> u64 {
+    offset.saturating_sub(OVERLAP)
+}
+
+// (first byte, whole size) from a Content-Range like "bytes 100-199/1000"
+fn content_range(header: &str) -> Option<(u64, Option<u64>)> {
+    let (range, size) = header.strip_prefix("bytes ")?.split_once('/')?;
+    let (first, _) = range.split_once('-')?;
+    Some((first.trim().parse().ok()?, size.trim().parse().ok()))
+}
+
+// Write the body to `part` as it arrives, passing on the progress. If `offset`
+// bytes are already there and the host sent the rest, carry on from there.
+// A host that sends more than the catalog says the book has is added to
+// `rejected`. Returns the size of the whole file.
+async fn save(
+    started: Started,
+    book: &BookRef,
+    part: &Path,
+    offset: u64,
+    shared: &Shared,
+    rejected: &mut Vec<String>,
+) -> Result<u64, Box<dyn error::Error>> {
+    let Started {
+        host,
+        mut response,
+        start,
+        total: expected,
+        resumed,
+        head,
+    } = started;
+    let catalog_size = u64::try_from(book.sizeinbytes).ok().filter(|n| *n > 0);
+    let mut progress = Progress {
+        received: start,
+        total: expected.or(catalog_size),
+        ..Default::default()
+    };
+    // the bytes on disk the response starts with, to be checked before any
+    // more are added
+    let mut overlap = Vec::new();
+    // a host that starts from the beginning again writes beside the partial
+    // download, which is only replaced once the new copy has got as far
+    let mut fresh = match (resumed, offset) {
+        (false, 1..) => {
+            let mut fresh = part.as_os_str().to_owned();
+            fresh.push(".new");
+            Some(PathBuf::from(fresh))
+        }
+        _ => None,
+    };
+    let mut file = match (resumed, &fresh) {
+        (true, _) => {
+            let mut file = std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(part)?;
+            file.seek(SeekFrom::Start(start))?;
+            file.read_to_end(&mut overlap)?;
+            BufWriter::new(file)
+        }
+        (false, Some(fresh)) => BufWriter::new(File::create(fresh)?),
+        (false, None) => BufWriter::new(File::create(part)?),
+    };
+    let started = Instant::now();
+    let mut reported = Instant::now();
+    shared.update(|status| {
+        status.description = match start {
+            0 => f!("Downloading {book.title}"),
+            _ => f!("Resuming {book.title}"),
+        }
+    });
+    // what `download_file` read to check comes first
+    let mut head = Some(Bytes::from(head));
+    let streamed: Result<(), Box<dyn error::Error>> = async {
+        loop {
+            let chunk = match head.take() {
+                Some(head) => head,
+                None => {
+                    let chunk = tokio::time::timeout(READ_TIMEOUT, response.chunk())
+                        .await
+                        .map_err(|_| "No data for 30 seconds")??;
+                    match chunk {
+                        Some(chunk) => chunk,
+                        None => break,
+                    }
+                }
+            };
+            if let Some(limit) = &shared.limit {
+                limit.take(chunk.len()).await;
+            }
+            let mut chunk: &[u8] = &chunk;
+            if resumed && progress.received < offset {
+                let n = overlap.len().min(chunk.len());
+                if overlap[..n] != chunk[..n] {
+                    // not the same file after all, so what's on disk is no use
+                    std::fs::remove_file(part)?;
+                    return Err("Partial download doesn't match, starting again".into());
+                }
+                overlap.drain(..n);
+                progress.received += n as u64;
+                chunk = &chunk[n..];
+            }
+            file.write_all(chunk)?;
+            progress.received += chunk.len() as u64;
+            if let Some(new) = &fresh {
+                if progress.received >= offset {
+                    file.flush()?;
+                    std::fs::rename(new, part)?;
+                    fresh = None;
+                }
+            }
+            if reported.elapsed() >= PROGRESS_INTERVAL {
+                let session = progress.received.saturating_sub(start);
+                progress.throughput = session as f64 / started.elapsed().as_secs_f64();
+                shared.set_progress(book, Some(progress.clone()));
+                reported = Instant::now();
+            }
+        }
+        file.flush()?;
+        file.get_ref().sync_all()?;
+        Ok(())
+    }
+    .await;
+    drop(file);
+    if let Some(fresh) = &fresh {
+        // it never got past what was already there
+        let _ = std::fs::remove_file(fresh);
+    }
+    streamed?;
+    if progress.received < offset {
+        return Err("Host sent less than was already downloaded".into());
+    }
+    if let Some(expected) = expected {
+        if progress.received != expected {
+            return Err(format!(
+                "Incomplete download: expected {} bytes, got {} bytes",
+                expected, progress.received
+            )
+            .into());
+        }
+    }
+    if let Some(size) = catalog_size {
+        // too little is resumed next time, but too much is the wrong file
+        if progress.received < size {
+            return Err(format!(
+                "Incomplete download: expected {} bytes, got {} bytes",
+                size, progress.received
+            )
+            .into());
+        }
+        if progress.received > size {
+            std::fs::remove_file(part)?;
+            rejected.push(host.clone());
+            return Err(format!(
+                "{} sent {} bytes, the book is {} bytes",
+                host, progress.received, size
+            )
+            .into());
+        }
+    }
+    let size = std::fs::metadata(part)?.len();
+    if size != progress.received {
+        return Err(format!(
+            "File size mismatch: expected {} bytes, got {} bytes",
+            progress.received, size
+        )
+        .into());
+    }
+    Ok(progress.received)
+}
+
+// A host that has started sending a book, and what it said about it
+struct Started {
+    host: String,
+    response: reqwest::Response,
+    // where in the file the response starts, and the size of the whole file
+    start: u64,
+    total: Option<u64>,
+    // whether the response carries on from a partial download
+    resumed: bool,
+    // the start of the body, already read to check it
+    head: Vec<u8>,
+}
+
+// Whichever host starts sending the book first. If `offset` bytes are already
+// downloaded, hosts are asked for the rest.
+async fn download_race(
+    hosts: Vec<String>,
+    book: &BookRef,
+    offset: u64,
+    shared: &Shared,
+) -> Result<Started, String> {
     // start a download for each host
-    let mut set = JoinSet::<Result<Bytes, String>>::new();
+    let mut set = JoinSet::<Result<Started, String>>::new();
 
     for (i, host) in hosts.iter().enumerate() {
         let host = host.clone();
         let book = book.clone();
+        let client = shared.client(&host).map_err(|e| e.to_string())?;
         set.spawn(async move {
             // give each endpoint an extra ten seconds to start
             let delay = Duration::from_secs(10 * i as u64);
             tokio::time::sleep(delay).await;
-            download_file(&host, &book).await.map_err(|e| e.to_string())
+            download_file(client, &host, &book, offset)
+                .await
+                .map_err(|e| e.to_string())
         });
     }
     while let Some(result) = set.join_next().await {
         match result {
-            Ok(Ok(bytes)) => {
+            Ok(Ok(started)) => {
                 set.abort_all(); // abort the rest of the downloads
-                return Ok(bytes);
+                return Ok(started);
             }
             Ok(Err(e)) => log::error!("Error downloading: {}", e),
             Err(e) => log::error!("Error joining download: {}", e),
@@ -157,10 +684,13 @@ async fn download_race(hosts: Vec<String>, book: &BookRef) -> Result<Bytes, Stri
     Err("No downloads succeeded".to_string())
 }
 
-async fn download_file(host: &String, book: &BookRef) -> Result<Bytes, Box<dyn error::Error>> {
-    let client = reqwest::Client::builder()
-        .timeout(Duration::from_secs(30))
-        .build()?;
+// Ask `host` for the book, and check it's sending what the catalog describes
+async fn download_file(
+    client: reqwest::Client,
+    host: &String,
+    book: &BookRef,
+    offset: u64,
+) -> Result<Started, Box<dyn error::Error>> {
     let series = if book.series.is_empty() {
         f!("")
     } else {
@@ -169,13 +699,374 @@ async fn download_file(host: &String, book: &BookRef) -> Result<Bytes, Box<dyn e
     let filename = f!("{series}{book.authors} - {book.title}.{book.format}");
     let url = f!("https://{host}/ipfs/{book.ipfs_cid}?filename={filename}");
 
-    let response = client.get(&url).send().await?;
+    // hosts that don't take ranges send the whole file, which `save` notices
+    let mut request = client.get(&url);
+    if offset > 0 {
+        request = request.header(reqwest::header::RANGE, f!("bytes={}-", resume_from(offset)));
+    }
+    let mut response = tokio::time::timeout(READ_TIMEOUT, request.send())
+        .await
+        .map_err(|_| format!("No response from {host}"))??;
+    if response.status() == reqwest::StatusCode::RANGE_NOT_SATISFIABLE {
+        // the file is shorter than what's on disk, so ask for all of it
+        response = tokio::time::timeout(READ_TIMEOUT, client.get(&url).send())
+            .await
+            .map_err(|_| format!("No response from {host}"))??;
+    }
     if !response.status().is_success() {
         return Err(format!("Error downloading {}: {}", url, response.status()).into());
     }
-    let result = response.bytes().await.map_err(|e| e.into());
-    if result.is_ok() {
-        log::info!("Downloaded {}", url);
+
+    let range = match response.status() {
+        reqwest::StatusCode::PARTIAL_CONTENT => response
+            .headers()
+            .get(reqwest::header::CONTENT_RANGE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(content_range),
+        _ => None,
+    };
+    let start = match range {
+        Some((start, _)) if offset > 0 && start == resume_from(offset) => start,
+        Some(_) => {
+            return Err(f!("{host} sent a different part of the file than asked for").into())
+        }
+        None => {
+            if offset > 0 {
+                log::info!("{} can't resume {}, starting again", host, book.title);
+            }
+            0
+        }
+    };
+    let total = match range {
+        Some((_, Some(size))) => Some(size),
+        _ => response.content_length().map(|length| start + length),
+    };
+    if let Some(total) = total {
+        if book.sizeinbytes > 0 && total != book.sizeinbytes as u64 {
+            return Err(
+                f!("{host} has a {total} byte file, the book is {book.sizeinbytes} bytes").into(),
+            );
+        }
+    }
+
+    // gateways answer with captchas and error pages as if they were the book
+    let mut head = Vec::new();
+    if start == 0 {
+        while head.len() < HEAD {
+            let chunk = tokio::time::timeout(READ_TIMEOUT, response.chunk())
+                .await
+                .map_err(|_| format!("No data from {host}"))??;
+            match chunk {
+                Some(chunk) => head.extend_from_slice(&chunk),
+                None => break,
+            }
+        }
+        check_magic(&book.format, &head).map_err(|e| f!("{host}: {e}"))?;
+    }
+    log::info!("Downloading {}", url);
+    Ok(Started {
+        host: host.clone(),
+        response,
+        start,
+        total,
+        resumed: range.is_some(),
+        head,
+    })
+}
+
+// how much of the start of a download is read to see what it is
+const HEAD: usize = 1024;
+
+// Whether the start of a file looks like a `format` file rather than a web page
+fn check_magic(format: &str, head: &[u8]) -> Result<(), String> {
+    let text = String::from_utf8_lossy(&head[..head.len().min(HEAD)]).to_lowercase();
+    let text = text.trim_start_matches('\u{feff}').trim_start();
+    let format = format.to_lowercase();
+    // a book can be a web page itself
+    let html = matches!(format.as_str(), "html" | "htm" | "xhtml" | "mht" | "mhtml");
+    if !html && (text.starts_with("<!doctype html") || text.starts_with("<html")) {
+        return Err("sent a web page instead of the book".to_string());
+    }
+    let looks_right = match format.as_str() {
+        "epub" | "zip" | "cbz" | "docx" | "odt" | "fbz" => head.starts_with(b"PK\x03\x04"),
+        // the header can be anywhere in the first KB
+        "pdf" => head.windows(5).any(|w| w == b"%PDF-"),
+        "djvu" | "djv" => head.starts_with(b"AT&TFORM"),
+        "mobi" | "azw" | "azw3" | "prc" => {
+            matches!(head.get(60..68), Some(b"BOOKMOBI") | Some(b"TEXtREAd"))
+        }
+        "fb2" => text.starts_with('<') && text.contains("<fictionbook"),
+        "rar" | "cbr" => head.starts_with(b"Rar!"),
+        "chm" => head.starts_with(b"ITSF"),
+        "rtf" => head.starts_with(b"{\\rtf"),
+        // nothing known to look for
+        _ => true,
+    };
+    match looks_right {
+        true => Ok(()),
+        false => Err(f!("sent something that isn't a {format} file")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_progress() {
+        let progress = Progress {
+            received: 1024 * 1024,
+            total: Some(4 * 1024 * 1024),
+            throughput: 512.0 * 1024.0,
+        };
+        assert_eq!(progress.fraction(), Some(0.25));
+        assert_eq!(progress.eta(), Some(Duration::from_secs(6)));
+        assert_eq!(
+            progress.to_string(),
+            "1.0 MB of 4.0 MB, 512.0 KB/s, 6s left"
+        );
+        let unknown = Progress {
+            received: 100,
+            ..Default::default()
+        };
+        assert_eq!(unknown.fraction(), None);
+        assert_eq!(unknown.eta(), None);
+        assert_eq!(unknown.to_string(), "100.0 B, 0.0 B/s");
+        // a total only if every download has one
+        let both = combine([&progress, &progress].into_iter()).unwrap();
+        assert_eq!(both.received, 2 * 1024 * 1024);
+        assert_eq!(both.total, Some(8 * 1024 * 1024));
+        assert_eq!(both.throughput, 1024.0 * 1024.0);
+        assert_eq!(
+            combine([&progress, &unknown].into_iter()).unwrap().total,
+            None
+        );
+        assert_eq!(combine([].into_iter()), None);
+        assert_eq!(
+            part_path(Path::new("books/Mort.epub")),
+            PathBuf::from("books/Mort.epub.part")
+        );
+    }
+
+    #[test]
+    fn test_check() {
+        let folder = std::env::temp_dir().join("rlgdesktop-test-check");
+        let _ = std::fs::remove_dir_all(&folder);
+        std::fs::create_dir_all(&folder).unwrap();
+        let config = Config::builder()
+            .set_override("downloadPath", folder.to_string_lossy().to_string())
+            .unwrap()
+            .build()
+            .unwrap();
+        let path = folder.join("Mort.epub");
+        let part = part_path(&path);
+        std::fs::write(&part, b"hello world\n").unwrap();
+        let book = |md5: &str| {
+            std::sync::Arc::new(crate::db::Book {
+                md5: md5.to_string(),
+                ..Default::default()
+            })
+        };
+        assert_eq!(
+            check(&book("6f5902ac237024bdd0c176cb93063dc4"), &part, &config),
+            Ok(())
+        );
+        let e = check(&book("d41d8cd98f00b204e9800998ecf8427e"), &part, &config).unwrap_err();
+        assert_eq!(
+            e,
+            "md5 is 6f5902ac237024bdd0c176cb93063dc4, expected d41d8cd98f00b204e9800998ecf8427e"
+        );
+        let e = quarantine(&part, &path, &config, e);
+        assert!(e.to_string().ends_with("moved to quarantine"));
+        assert!(!part.exists());
+        assert!(folder.join("quarantine").join("Mort.epub").exists());
+        // with `verifyCid` on, the CID has to match as well
+        std::fs::write(&part, b"hello world\n").unwrap();
+        let config = Config::builder()
+            .add_source(config)
+            .set_override("verifyCid", true)
+            .unwrap()
+            .build()
+            .unwrap();
+        let book = std::sync::Arc::new(crate::db::Book {
+            md5: "6f5902ac237024bdd0c176cb93063dc4".to_string(),
+            ipfs_cid: "QmbFMke1KXqnYyBBWxB74N4c5SBnJMVAiMNRcGu6x1AwQH".to_string(),
+            ..Default::default()
+        });
+        assert_eq!(
+            check(&book, &part, &config),
+            Err(
+                "CID is QmT78zSuBmuS4z925WZfrqQ1qHaJ56DQaTfyMUF7F8ff5o, expected QmbFMke1KXqnYyBBWxB74N4c5SBnJMVAiMNRcGu6x1AwQH"
+                    .to_string()
+            )
+        );
+        let _ = std::fs::remove_dir_all(&folder);
+    }
+
+    #[test]
+    fn test_claim() {
+        let shared = Shared::new(Config::default(), unbounded().0);
+        let book = |cid: &str| {
+            std::sync::Arc::new(crate::db::Book {
+                ipfs_cid: cid.to_string(),
+                download_path: PathBuf::from("books/Mort.epub"),
+                ..Default::default()
+            })
+        };
+        let (first, second) = (book("a"), book("b"));
+        assert!(shared.claim(&first));
+        assert!(!shared.claim(&second));
+        *first.download_status.write().unwrap() = "Done".to_string();
+        shared.release(&first);
+        assert_eq!(*second.download_status.read().unwrap(), "Done");
+        // free for the next one
+        assert!(shared.claim(&second));
+    }
+
+    #[test]
+    fn test_magic() {
+        let mut mobi = vec![0; 60];
+        mobi.extend_from_slice(b"BOOKMOBI");
+        assert_eq!(check_magic("EPUB", b"PK\x03\x04\x14\x00"), Ok(()));
+        assert_eq!(check_magic("pdf", b"\n%PDF-1.4"), Ok(()));
+        assert_eq!(check_magic("djvu", b"AT&TFORM\x00"), Ok(()));
+        assert_eq!(check_magic("mobi", &mobi), Ok(()));
+        assert_eq!(
+            check_magic(
+                "fb2",
+                "\u{feff}<?xml version=\"1.0\"?>\n<FictionBook>".as_bytes()
+            ),
+            Ok(())
+        );
+        assert_eq!(check_magic("txt", b"anything"), Ok(()));
+        assert_eq!(
+            check_magic("html", b"<html><head><title>Mort</title>"),
+            Ok(())
+        );
+        assert_eq!(check_magic("XHTML", b"<!DOCTYPE html>\n<html>"), Ok(()));
+        assert_eq!(
+            check_magic("epub", b"<!DOCTYPE html><title>Captcha</title>"),
+            Err("sent a web page instead of the book".to_string())
+        );
+        assert_eq!(
+            check_magic("txt", b"  <html><body>502 Bad Gateway"),
+            Err("sent a web page instead of the book".to_string())
+        );
+        assert_eq!(
+            check_magic("pdf", b"PK\x03\x04"),
+            Err("sent something that isn't a pdf file".to_string())
+        );
+        assert!(check_magic("fb2", b"<?xml version=\"1.0\"?><error/>").is_err());
+        assert!(check_magic("mobi", b"short").is_err());
+    }
+
+    #[test]
+    fn test_limit() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let limit = Limit::new(1000);
+        let started = Instant::now();
+        runtime.block_on(async {
+            limit.take(100).await;
+            limit.take(100).await;
+        });
+        assert!(started.elapsed() >= Duration::from_millis(200));
+    }
+
+    // what a host sends: a server on a free port that answers one request
+    // with `body`
+    fn serve(body: Vec<u8>) -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut socket, _) = listener.accept().unwrap();
+            let mut request = [0; 4096];
+            let _ = socket.read(&mut request);
+            let header = format!(
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                body.len()
+            );
+            socket.write_all(header.as_bytes()).unwrap();
+            socket.write_all(&body).unwrap();
+        });
+        format!("http://{address}/")
+    }
+
+    #[test]
+    fn test_save() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let shared = Shared::new(Config::default(), unbounded().0);
+        let book = std::sync::Arc::new(crate::db::Book {
+            sizeinbytes: 12_000,
+            ..Default::default()
+        });
+        let part = std::env::temp_dir().join("rlgdesktop-test-save.part");
+        let whole: Vec<u8> = (0..12_000).map(|i| (i % 251) as u8).collect();
+        // the host sends `body` starting `start` bytes into the file
+        let save_from = |body: &[u8], start: u64, resumed: bool, offset: u64| {
+            let url = serve(body.to_vec());
+            runtime.block_on(async {
+                let started = Started {
+                    host: "host".to_string(),
+                    response: reqwest::get(url).await.unwrap(),
+                    start,
+                    total: None,
+                    resumed,
+                    head: vec![],
+                };
+                save(started, &book, &part, offset, &shared, &mut vec![])
+                    .await
+                    .map_err(|e| e.to_string())
+            })
+        };
+
+        // resumed from where the partial download stopped
+        std::fs::write(&part, &whole[..10_000]).unwrap();
+        let start = resume_from(10_000);
+        let resumed = save_from(&whole[start as usize..], start, true, 10_000);
+        assert_eq!(resumed, Ok(12_000));
+        assert_eq!(std::fs::read(&part).unwrap(), whole);
+
+        // the overlap isn't what's on disk, so it was another file
+        let mut other = whole.clone();
+        other[start as usize] ^= 1;
+        std::fs::write(&part, &other[..10_000]).unwrap();
+        let mismatch = save_from(&whole[start as usize..], start, true, 10_000);
+        assert_eq!(
+            mismatch,
+            Err("Partial download doesn't match, starting again".to_string())
+        );
+        assert!(!part.exists());
+
+        // a host that starts again and stops short leaves the partial alone
+        std::fs::write(&part, &whole[..10_000]).unwrap();
+        let short = save_from(&whole[..3_000], 0, false, 10_000);
+        assert_eq!(
+            short,
+            Err("Host sent less than was already downloaded".to_string())
+        );
+        assert_eq!(std::fs::read(&part).unwrap(), &whole[..10_000]);
+        // and one that gets further replaces it
+        assert_eq!(save_from(&whole, 0, false, 10_000), Ok(12_000));
+        assert_eq!(std::fs::read(&part).unwrap(), whole);
+        assert!(!std::env::temp_dir()
+            .join("rlgdesktop-test-save.part.new")
+            .exists());
+        let _ = std::fs::remove_file(&part);
+    }
+
+    #[test]
+    fn test_resume() {
+        assert_eq!(resume_from(100), 0);
+        assert_eq!(resume_from(10_000), 10_000 - OVERLAP);
+        assert_eq!(content_range("bytes 100-199/1000"), Some((100, Some(1000))));
+        assert_eq!(content_range("bytes 100-199/*"), Some((100, None)));
+        assert_eq!(content_range("bytes */1000"), None);
+        assert_eq!(content_range("items 1-2/3"), None);
     }
-    result
 }
@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+
+use crate::db::{parse_year, Book, Params};
+
+// values shown for each facet
+const TOP: usize = 5;
+
+// How many of the rows read so far have each language, format and decade.
+// Updated batch by batch as results stream in.
+#[derive(Default)]
+pub struct Facets {
+    // keyed on the lowercased value, with the first spelling seen for display
+    languages: HashMap<String, (String, usize)>,
+    formats: HashMap<String, (String, usize)>,
+    // keyed on the first year of the decade
+    decades: HashMap<i64, usize>,
+}
+
+impl Facets {
+    pub fn add(&mut self, book: &Book) {
+        count(&mut self.languages, &book.language);
+        count(&mut self.formats, &book.format);
+        if let Some(year) = parse_year(&book.year) {
+            *self.decades.entry(year - year.rem_euclid(10)).or_default() += 1;
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.languages.is_empty() && self.formats.is_empty() && self.decades.is_empty()
+    }
+}
+
+fn count(counts: &mut HashMap<String, (String, usize)>, value: &str) {
+    let value = value.trim();
+    // nothing to filter on
+    if value.is_empty() {
+        return;
+    }
+    counts
+        .entry(value.to_lowercase())
+        .or_insert_with(|| (value.to_string(), 0))
+        .1 += 1;
+}
+
+// the most common values first, ties in alphabetical order
+fn top<K: Ord + Clone>(counts: impl Iterator<Item = (K, usize)>) -> Vec<(K, usize)> {
+    let mut counts: Vec<(K, usize)> = counts.collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    counts.truncate(TOP);
+    counts
+}
+
+// Returns true if a value was clicked and the filters changed. Clicking the
+// value that's already being filtered on clears that filter.
+pub fn render(ui: &mut egui::Ui, facets: &Facets, filters: &mut Params) -> bool {
+    let mut changed = false;
+    ui.label("Language");
+    let languages = top(facets.languages.values().cloned());
+    changed |= render_values(ui, languages, &mut filters.language);
+    ui.label("Format");
+    let formats = top(facets.formats.values().cloned());
+    changed |= render_values(ui, formats, &mut filters.format);
+
+    ui.label("Decade");
+    ui.horizontal_wrapped(|ui| {
+        for (decade, n) in top(facets.decades.iter().map(|(d, n)| (*d, *n))) {
+            let selected = filters.min_year == Some(decade) && filters.max_year == Some(decade + 9);
+            if ui
+                .selectable_label(selected, format!("{decade}s ({n})"))
+                .clicked()
+            {
+                (filters.min_year, filters.max_year) = match selected {
+                    true => (None, None),
+                    false => (Some(decade), Some(decade + 9)),
+                };
+                changed = true;
+            }
+        }
+    });
+    changed
+}
+
+fn render_values(ui: &mut egui::Ui, values: Vec<(String, usize)>, filter: &mut String) -> bool {
+    let mut changed = false;
+    ui.horizontal_wrapped(|ui| {
+        for (value, n) in values {
+            let selected = filter.eq_ignore_ascii_case(&value);
+            if ui
+                .selectable_label(selected, format!("{value} ({n})"))
+                .clicked()
+            {
+                *filter = match selected {
+                    true => String::new(),
+                    false => value,
+                };
+                changed = true;
+            }
+        }
+    });
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_facets() {
+        let mut facets = Facets::default();
+        for (language, format, year) in [
+            ("English", "epub", "1989"),
+            ("english", "pdf", "c1991"),
+            ("Russian", "epub", "1999-2001"),
+            ("", "EPUB", ""),
+        ] {
+            facets.add(&Book {
+                language: language.to_string(),
+                format: format.to_string(),
+                year: year.to_string(),
+                ..Default::default()
+            });
+        }
+        assert_eq!(
+            top(facets.languages.values().cloned()),
+            vec![("English".to_string(), 2), ("Russian".to_string(), 1)]
+        );
+        assert_eq!(
+            top(facets.formats.values().cloned()),
+            vec![("epub".to_string(), 3), ("pdf".to_string(), 1)]
+        );
+        assert_eq!(
+            top(facets.decades.iter().map(|(d, n)| (*d, *n))),
+            vec![(1990, 2), (1980, 1)]
+        );
+    }
+}
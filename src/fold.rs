@@ -0,0 +1,117 @@
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+
+// Text as the filters compare it: lowercase, without accents, and with
+// Cyrillic and Greek spelt out in Latin letters if `transliterate`, so that
+// "García Márquez" is "garcia marquez" and "Достоевский" is "dostoevskii".
+pub fn fold(text: &str, transliterate: bool) -> String {
+    let mut folded = String::with_capacity(text.len());
+    // é is e followed by a combining accent once decomposed
+    for c in text.nfd().filter(|c| !is_combining_mark(*c)) {
+        for c in c.to_lowercase() {
+            match letters(c, transliterate) {
+                Some(letters) => folded.push_str(letters),
+                None => folded.push(c),
+            }
+        }
+    }
+    folded
+}
+
+// the Latin spelling of letters that don't decompose into one
+fn letters(c: char, transliterate: bool) -> Option<&'static str> {
+    let latin = match c {
+        'ß' => "ss",
+        'æ' => "ae",
+        'œ' => "oe",
+        'ø' => "o",
+        'đ' | 'ð' => "d",
+        'ł' => "l",
+        'þ' => "th",
+        'ı' => "i",
+        _ => "",
+    };
+    if !latin.is_empty() {
+        return Some(latin);
+    }
+    if !transliterate {
+        return None;
+    }
+    let cyrillic = match c {
+        'а' => "a",
+        'б' => "b",
+        'в' => "v",
+        'г' | 'ґ' => "g",
+        'д' => "d",
+        'е' | 'э' => "e",
+        'є' => "ye",
+        'ж' => "zh",
+        'з' => "z",
+        'и' | 'і' | 'ї' | 'й' => "i",
+        'к' => "k",
+        'л' => "l",
+        'м' => "m",
+        'н' => "n",
+        'о' => "o",
+        'п' => "p",
+        'р' => "r",
+        'с' => "s",
+        'т' => "t",
+        'у' | 'ў' => "u",
+        'ф' => "f",
+        'х' => "kh",
+        'ц' => "ts",
+        'ч' => "ch",
+        'ш' => "sh",
+        'щ' => "shch",
+        'ъ' | 'ь' => "",
+        'ы' => "y",
+        'ю' => "yu",
+        'я' => "ya",
+        _ => return greek(c),
+    };
+    Some(cyrillic)
+}
+
+fn greek(c: char) -> Option<&'static str> {
+    Some(match c {
+        'α' => "a",
+        'β' => "v",
+        'γ' => "g",
+        'δ' => "d",
+        'ε' => "e",
+        'ζ' => "z",
+        'η' | 'ι' => "i",
+        'θ' => "th",
+        'κ' => "k",
+        'λ' => "l",
+        'μ' => "m",
+        'ν' => "n",
+        'ξ' => "x",
+        'ο' | 'ω' => "o",
+        'π' => "p",
+        'ρ' => "r",
+        'σ' | 'ς' => "s",
+        'τ' => "t",
+        'υ' => "y",
+        'φ' => "f",
+        'χ' => "ch",
+        'ψ' => "ps",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fold() {
+        assert_eq!(fold("García Márquez", false), "garcia marquez");
+        assert_eq!(fold("Straße, Ærøskøbing", false), "strasse, aeroskobing");
+        assert_eq!(fold("Достоевский", false), "достоевскии");
+        assert_eq!(fold("Достоевский", true), "dostoevskii");
+        assert_eq!(fold("Щедрин, Ёлка", true), "shchedrin, elka");
+        assert_eq!(fold("Οδύσσεια", true), "odysseia");
+        assert_eq!(fold("100% Pure", true), "100% pure");
+    }
+}
@@ -0,0 +1,202 @@
+use std::collections::{HashMap, HashSet};
+
+// words shorter than this have too few trigrams to find anything alike
+const MIN_WORD: usize = 3;
+// index rows read for each word, before they're scored properly
+const CANDIDATES: usize = 200;
+// words less alike than this aren't what was meant
+const MIN_SIMILARITY: f64 = 0.3;
+
+// A word as the full-text index keeps it: lowercase letters and digits only
+pub fn normalize(word: &str) -> String {
+    word.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+// padded like pg_trgm so that the start and end of a word count for more
+fn trigrams(word: &str) -> HashSet<[char; 3]> {
+    let word = normalize(word);
+    if word.is_empty() {
+        return HashSet::new();
+    }
+    let chars: Vec<char> = format!("  {word} ").chars().collect();
+    chars.windows(3).map(|w| [w[0], w[1], w[2]]).collect()
+}
+
+fn jaccard(a: &HashSet<[char; 3]>, b: &HashSet<[char; 3]>) -> f64 {
+    let shared = a.intersection(b).count();
+    match a.len() + b.len() - shared {
+        0 => 0.0,
+        all => shared as f64 / all as f64,
+    }
+}
+
+// How alike two words are, from 0 to 1: the share of their trigrams in common
+pub fn similarity(a: &str, b: &str) -> f64 {
+    jaccard(&trigrams(a), &trigrams(b))
+}
+
+// How well some text matches the words searched for: the closest word in the
+// text to each of them, averaged
+pub fn text_similarity(text: &str, words: &str) -> f64 {
+    let text: Vec<HashSet<[char; 3]>> = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(trigrams)
+        .collect();
+    let words: Vec<HashSet<[char; 3]>> = words.split_whitespace().map(trigrams).collect();
+    if words.is_empty() {
+        return 0.0;
+    }
+    let total: f64 = words
+        .iter()
+        .map(|word| text.iter().map(|t| jaccard(word, t)).fold(0.0, f64::max))
+        .sum();
+    total / words.len() as f64
+}
+
+// Build the trigram index of the title and author words in a `_mini` table's
+// full-text index if it isn't there yet. Each distinct word is a row, with
+// the number of books it's in.
+pub fn ensure_trigrams(
+    connection: &rusqlite::Connection,
+    alias: &str,
+    table: &str,
+) -> rusqlite::Result<()> {
+    let exists: bool = connection.query_row(
+        &format!("SELECT count(*) > 0 FROM {alias}.sqlite_master WHERE name = ?1"),
+        [format!("{table}_trigrams")],
+        |row| row.get(0),
+    )?;
+    if exists {
+        return Ok(());
+    }
+    log::info!("Building fuzzy search index for {}.{}", alias, table);
+    connection.execute_batch("BEGIN")?;
+    let result = connection.execute_batch(&format!(
+        "
+        CREATE VIRTUAL TABLE temp.{alias}_{table}_vocab USING fts5vocab({alias}, {table}_fts, 'col');
+        CREATE VIRTUAL TABLE {alias}.{table}_trigrams USING fts5(
+            term, books UNINDEXED, tokenize='trigram'
+        );
+        INSERT INTO {alias}.{table}_trigrams (term, books)
+        SELECT term, max(doc) FROM temp.{alias}_{table}_vocab
+        WHERE col IN ('Title', 'Author') AND length(term) >= {MIN_WORD}
+        GROUP BY term;
+        DROP TABLE temp.{alias}_{table}_vocab;
+        "
+    ));
+    // leave nothing half built behind to be mistaken for the real thing
+    connection.execute_batch(match result {
+        Ok(_) => "COMMIT",
+        Err(_) => "ROLLBACK",
+    })?;
+    result?;
+    log::info!("Built fuzzy search index for {}.{}", alias, table);
+    Ok(())
+}
+
+// Words in the trigram indexes of `tables` (`alias.table` of `_mini` tables)
+// that are like `word`, as (word, similarity, books), the most alike first
+pub fn similar_words(
+    connection: &rusqlite::Connection,
+    tables: &[String],
+    word: &str,
+) -> rusqlite::Result<Vec<(String, f64, i64)>> {
+    let word = normalize(word);
+    let chars: Vec<char> = word.chars().collect();
+    if chars.len() < MIN_WORD {
+        return Ok(vec![]);
+    }
+    // any trigram will do; the index ranks words with more of them first
+    let grams: HashSet<String> = chars.windows(3).map(|w| w.iter().collect()).collect();
+    let grams: Vec<String> = grams.iter().map(|g| format!("\"{g}\"")).collect();
+    let grams = grams.join(" OR ");
+    let mut books: HashMap<String, i64> = HashMap::new();
+    for table in tables {
+        let name = table.rsplit('.').next().unwrap_or(table);
+        let mut stmt = connection.prepare(&format!(
+            "
+            SELECT term, books FROM {table}_trigrams
+            WHERE {name}_trigrams MATCH ?1
+            ORDER BY rank
+            LIMIT {CANDIDATES}
+            "
+        ))?;
+        let rows = stmt.query_map([&grams], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        for row in rows {
+            let (term, n): (String, i64) = row?;
+            *books.entry(term).or_default() += n;
+        }
+    }
+    let mut words: Vec<(String, f64, i64)> = books
+        .into_iter()
+        .map(|(term, n)| {
+            let score = similarity(&word, &term);
+            (term, score, n)
+        })
+        .filter(|(_, score, _)| *score >= MIN_SIMILARITY)
+        .collect();
+    words.sort_by(|a, b| {
+        b.1.partial_cmp(&a.1)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then_with(|| b.2.cmp(&a.2))
+            .then_with(|| a.0.cmp(&b.0))
+    });
+    Ok(words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_similarity() {
+        assert_eq!(similarity("Pratchett", "pratchett"), 1.0);
+        assert!(similarity("pratchet", "pratchett") > 0.7);
+        assert!(similarity("dostoyevsky", "dostoevsky") > 0.5);
+        assert!(similarity("dostoyevsky", "pratchett") < MIN_SIMILARITY);
+        assert_eq!(similarity("", ""), 0.0);
+        assert_eq!(
+            text_similarity("Mort; Pratchett, Terry", "pratchett mort"),
+            1.0
+        );
+        assert!(text_similarity("Mort; Pratchett, Terry", "pratchet") > 0.7);
+        assert_eq!(text_similarity("Mort", ""), 0.0);
+    }
+
+    #[test]
+    fn test_similar_words() {
+        let connection = rusqlite::Connection::open_in_memory().unwrap();
+        connection
+            .execute_batch(
+                "
+                CREATE TABLE fiction_mini (Title, Author, Series, Publisher);
+                INSERT INTO fiction_mini VALUES ('Crime and Punishment', 'Dostoevsky, Fyodor', '', '');
+                INSERT INTO fiction_mini VALUES ('The Idiot', 'Dostoevsky, Fyodor', '', 'Penguin');
+                INSERT INTO fiction_mini VALUES ('Mort', 'Pratchett, Terry', '', 'Penguin');
+                CREATE VIRTUAL TABLE fiction_mini_fts USING fts5(Title, Author, Series, Publisher, content='fiction_mini', content_rowid='rowid');
+                INSERT INTO fiction_mini_fts(fiction_mini_fts) VALUES('rebuild');
+                ",
+            )
+            .unwrap();
+        ensure_trigrams(&connection, "main", "fiction_mini").unwrap();
+        // a second time does nothing
+        ensure_trigrams(&connection, "main", "fiction_mini").unwrap();
+        let tables = ["main.fiction_mini".to_string()];
+        let words = similar_words(&connection, &tables, "Dostoyevsky").unwrap();
+        assert_eq!(words[0].0, "dostoevsky");
+        assert_eq!(words[0].2, 2);
+        let words = similar_words(&connection, &tables, "pratchet").unwrap();
+        assert_eq!(words[0].0, "pratchett");
+        // only titles and authors are indexed
+        assert!(similar_words(&connection, &tables, "penguin")
+            .unwrap()
+            .is_empty());
+        assert!(similar_words(&connection, &tables, "xy")
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,1259 @@
+// Build the compact catalog straight from a libgen MySQL dump.
+//
+// This replaces the `convert_db_dump.py` / `mysql2sqlite.sh` / `sqlite3` /
+// `compress-*.sql` pipeline in `sql/`. The dump is streamed one statement at a
+// time, so memory use is bounded by the largest single `INSERT` rather than the
+// size of the file. Only the columns that end up in the `_mini` tables are kept.
+//
+// With `--update` a newer dump, or one holding only the rows that changed, is
+// merged into an existing catalog instead, matching rows on their md5.
+
+use std::{
+    error,
+    fs::File,
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant, UNIX_EPOCH},
+};
+
+use rusqlite::{types::Value, Connection};
+
+use crate::{config::load_settings, db::db_paths};
+
+// tables we keep from the dumps, and the columns we keep from each
+const TABLES: &[(&str, &[&str])] = &[
+    (
+        "fiction",
+        &[
+            "MD5",
+            "Title",
+            "Author",
+            "Series",
+            "Language",
+            "Year",
+            "Publisher",
+            "Extension",
+            "Filesize",
+            "Locator",
+        ],
+    ),
+    ("fiction_hashes", &["md5", "ipfs_cid"]),
+    // non-fiction (the main libgen dump)
+    (
+        "updated",
+        &[
+            "MD5",
+            "Title",
+            "Author",
+            "Series",
+            "Language",
+            "Year",
+            "Publisher",
+            "Extension",
+            "Filesize",
+            "Locator",
+            "Topic",
+        ],
+    ),
+    ("hashes", &["md5", "ipfs_cid"]),
+    (
+        "topics",
+        &[
+            "topic_descr",
+            "lang",
+            "kolxoz_code",
+            "topic_id",
+            "topic_id_hlm",
+        ],
+    ),
+];
+
+// rows per transaction while loading
+const BATCH_ROWS: usize = 100_000;
+
+// `rlgdesktop import [--output catalog.sqlite] [--update [--prune]] dump.sql...`
+pub fn run(args: &[String]) -> Result<(), Box<dyn error::Error>> {
+    let mut output = None;
+    let mut update_existing = false;
+    let mut prune = false;
+    let mut dumps = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output" => output = args.next().map(PathBuf::from),
+            "-u" | "--update" => update_existing = true,
+            "--prune" => prune = true,
+            _ => dumps.push(PathBuf::from(arg)),
+        }
+    }
+    if dumps.is_empty() || (prune && !update_existing) {
+        return Err(
+            "usage: rlgdesktop import [--output catalog.sqlite] [--update [--prune]] dump.sql..."
+                .into(),
+        );
+    }
+    let output = match output {
+        Some(output) => output,
+        None => match db_paths(&load_settings()).first() {
+            Some(path) if !path.is_empty() => PathBuf::from(path),
+            _ => dumps[0].with_extension("sqlite"),
+        },
+    };
+    match update_existing {
+        true => update(&dumps, &output, prune),
+        false => import(&dumps, &output),
+    }
+}
+
+pub fn import(dumps: &[PathBuf], output: &Path) -> Result<(), Box<dyn error::Error>> {
+    let connection = Connection::open(output)?;
+    let dump_date = stage(&connection, dumps)?;
+    eprintln!("Building catalog tables");
+    compress(&connection, dump_date.as_deref())?;
+    eprintln!("Compacting {}", output.display());
+    connection.execute_batch("VACUUM;")?;
+    eprintln!("Done: {}", output.display());
+    Ok(())
+}
+
+// Merge newer dumps into the catalog at `output`. With `prune` the dumps are
+// taken to be complete, so books that aren't in them any more are removed.
+pub fn update(dumps: &[PathBuf], output: &Path, prune: bool) -> Result<(), Box<dyn error::Error>> {
+    if !output.exists() {
+        return Err(format!("{} does not exist, import it first", output.display()).into());
+    }
+    let connection = Connection::open(output)?;
+    let dump_date = stage(&connection, dumps)?;
+    eprintln!("Applying update");
+    for changes in apply_update(&connection, dump_date.as_deref(), prune)? {
+        eprintln!(
+            "{}: {} added, {} changed, {} removed",
+            changes.table, changes.added, changes.changed, changes.removed
+        );
+    }
+    drop_staging(&connection)?;
+    eprintln!("Done: {}", output.display());
+    Ok(())
+}
+
+// Load the dumps into the staging tables. Returns when the newest of them was
+// made, from its "Dump completed on" line or else from the file's timestamp.
+fn stage(
+    connection: &Connection,
+    dumps: &[PathBuf],
+) -> Result<Option<String>, Box<dyn error::Error>> {
+    connection.execute_batch(
+        "
+        PRAGMA synchronous = OFF;
+        PRAGMA journal_mode = MEMORY;
+        ",
+    )?;
+    for (table, columns) in TABLES {
+        create_staging_table(connection, table, columns)?;
+    }
+    let mut newest: Option<String> = None;
+    for dump in dumps {
+        let file = File::open(dump)?;
+        let metadata = file.metadata()?;
+        eprintln!("Reading {}", dump.display());
+        let mut progress = Progress::new(metadata.len());
+        let completed = load_dump(BufReader::new(file), connection, &mut progress)?;
+        progress.finish();
+        let date = match completed {
+            Some(date) => date,
+            None => {
+                let modified = metadata.modified()?.duration_since(UNIX_EPOCH)?.as_secs() as i64;
+                connection.query_row("SELECT datetime(?1, 'unixepoch')", [modified], |row| {
+                    row.get(0)
+                })?
+            }
+        };
+        newest = newest.max(Some(date));
+    }
+    for (_, _, hashes, _) in COLLECTIONS {
+        // for the join to the books
+        connection.execute_batch(&format!(
+            "CREATE INDEX {hashes}_md5 ON {hashes} (md5);",
+            hashes = staging(hashes)
+        ))?;
+    }
+    Ok(newest)
+}
+
+fn create_staging_table(
+    connection: &Connection,
+    table: &str,
+    columns: &[&str],
+) -> rusqlite::Result<()> {
+    let columns = columns
+        .iter()
+        .map(|c| format!("`{}` {}", c, column_type(c)))
+        .collect::<Vec<_>>()
+        .join(", ");
+    connection.execute_batch(&format!(
+        "
+        DROP TABLE IF EXISTS {table};
+        CREATE TABLE {table} ({columns});
+        ",
+        table = staging(table)
+    ))
+}
+
+fn column_type(column: &str) -> &'static str {
+    match column {
+        "Filesize" | "topic_id" | "topic_id_hlm" => "INTEGER",
+        _ => "TEXT",
+    }
+}
+
+// the catalog tables, the staged dump tables they're built from, and the
+// columns they keep besides MD5
+const COLLECTIONS: &[(&str, &str, &str, &[&str])] = &[
+    ("fiction_mini", "fiction", "fiction_hashes", MINI_COLUMNS),
+    ("non_fiction_mini", "updated", "hashes", NON_FICTION_COLUMNS),
+];
+const MINI_COLUMNS: &[&str] = &[
+    "ipfs_cid",
+    "Title",
+    "Author",
+    "Series",
+    "Language",
+    "Year",
+    "Publisher",
+    "Extension",
+    "Filesize",
+    "Locator",
+];
+const NON_FICTION_COLUMNS: &[&str] = &[
+    "ipfs_cid",
+    "Title",
+    "Author",
+    "Series",
+    "Language",
+    "Year",
+    "Publisher",
+    "Extension",
+    "Filesize",
+    "Locator",
+    "Topic",
+];
+
+// Same result as running `compress-fiction.sql` and `compress-libgren.sql`.
+// A collection whose dump wasn't part of this import is left as it was.
+fn compress(connection: &Connection, dump_date: Option<&str>) -> rusqlite::Result<()> {
+    create_meta_table(connection)?;
+    for (mini, books, hashes, columns) in COLLECTIONS {
+        if staged_rows(connection, books)? == 0 {
+            continue;
+        }
+        connection.execute_batch(&format!(
+            "
+            DROP TABLE IF EXISTS {mini}_fts;
+            DROP TABLE IF EXISTS {mini}_trigrams;
+            DROP TABLE IF EXISTS {mini};
+            CREATE TABLE {mini} AS {};
+            CREATE INDEX idx_{mini}_Search ON {mini} (`Author`, `Title`, `Series`, `Language`, `Extension`);
+            CREATE INDEX idx_{mini}_MD5 ON {mini} (`MD5`);
+            ",
+            select_books(books, hashes, columns),
+        ))?;
+        let kept: i64 =
+            connection.query_row(&format!("SELECT count(*) FROM {mini}"), [], |row| {
+                row.get(0)
+            })?;
+        eprintln!("{}: {} rows", mini, kept);
+        record_dump(
+            connection,
+            &Changes {
+                table: mini,
+                added: kept as usize,
+                ..Default::default()
+            },
+            dump_date,
+        )?;
+    }
+    if staged_rows(connection, "topics")? > 0 {
+        connection.execute_batch(&format!(
+            "
+            DROP TABLE IF EXISTS topics;
+            ALTER TABLE {} RENAME TO topics;
+            ",
+            staging("topics")
+        ))?;
+    }
+    drop_staging(connection)
+}
+
+fn drop_staging(connection: &Connection) -> rusqlite::Result<()> {
+    for (table, _) in TABLES {
+        connection.execute_batch(&format!("DROP TABLE IF EXISTS {};", staging(table)))?;
+    }
+    Ok(())
+}
+
+fn staged_rows(connection: &Connection, table: &str) -> rusqlite::Result<i64> {
+    connection.query_row(
+        &format!("SELECT count(*) FROM {}", staging(table)),
+        [],
+        |row| row.get(0),
+    )
+}
+
+// The catalog rows for the staged books: those with an IPFS CID, md5 in lower case
+fn select_books(books: &str, hashes: &str, columns: &[&str]) -> String {
+    let (books, hashes) = (staging(books), staging(hashes));
+    let columns: Vec<String> = columns
+        .iter()
+        .map(|c| match *c {
+            "ipfs_cid" => "fh.ipfs_cid".to_string(),
+            c => format!("f.{c}"),
+        })
+        .collect();
+    format!(
+        "
+        select {}, lower(f.MD5) AS MD5
+        from {books} f
+        join {hashes} fh on fh.md5 = lower(f.md5)
+        where fh.ipfs_cid is not null and trim(fh.ipfs_cid) <> ''
+        ",
+        columns.join(", ")
+    )
+}
+
+// What applying a dump did to one catalog table
+#[derive(Debug, Default, PartialEq)]
+struct Changes {
+    table: &'static str,
+    added: usize,
+    changed: usize,
+    removed: usize,
+}
+
+// Merge the staged rows into the `_mini` tables: new md5s are added, and rows
+// whose md5 is already there are overwritten if anything the dump has differs.
+fn apply_update(
+    connection: &Connection,
+    dump_date: Option<&str>,
+    prune: bool,
+) -> Result<Vec<Changes>, Box<dyn error::Error>> {
+    create_meta_table(connection)?;
+    let mut applied = Vec::new();
+    for (mini, books, hashes, columns) in COLLECTIONS {
+        if staged_rows(connection, books)? == 0 {
+            continue;
+        }
+        let has_md5: bool = connection.query_row(
+            "SELECT count(*) > 0 FROM pragma_table_info(?1) WHERE name = 'MD5'",
+            [mini],
+            |row| row.get(0),
+        )?;
+        let exists: bool = connection.query_row(
+            "SELECT count(*) > 0 FROM sqlite_master WHERE name = ?1",
+            [mini],
+            |row| row.get(0),
+        )?;
+        if exists && !has_md5 {
+            return Err(format!(
+                "{mini} was built without md5s, so rows can't be matched up; import it again without --update"
+            )
+            .into());
+        }
+        let newest: Option<String> = connection.query_row(
+            "SELECT max(dump_date) FROM catalog_meta WHERE collection = ?1",
+            [mini],
+            |row| row.get(0),
+        )?;
+        if let (Some(newest), Some(date)) = (&newest, dump_date) {
+            if date < newest.as_str() {
+                eprintln!("Warning: this dump ({date}) is older than the last one applied to {mini} ({newest})");
+            }
+        }
+
+        let select = select_books(books, hashes, columns);
+        if !exists {
+            // nothing to merge with
+            connection.execute_batch(&format!(
+                "
+                CREATE TABLE {mini} AS {select};
+                CREATE INDEX idx_{mini}_Search ON {mini} (`Author`, `Title`, `Series`, `Language`, `Extension`);
+                CREATE INDEX idx_{mini}_MD5 ON {mini} (`MD5`);
+                "
+            ))?;
+        }
+        connection.execute_batch(&format!(
+            "
+            BEGIN;
+            DROP TABLE IF EXISTS temp.incoming;
+            CREATE TEMP TABLE incoming AS {select};
+            CREATE INDEX temp.incoming_md5 ON incoming (MD5);
+            "
+        ))?;
+        let mut changes = Changes {
+            table: mini,
+            ..Default::default()
+        };
+        if exists {
+            let list = columns.join(", ");
+            // a column the dump left out is staged as NULL, which keeps what's there
+            let merged = columns
+                .iter()
+                .map(|c| format!("coalesce(n.{c}, m.{c})"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            let same = columns
+                .iter()
+                .map(|c| format!("m.{c} IS coalesce(n.{c}, m.{c})"))
+                .collect::<Vec<_>>()
+                .join(" AND ");
+            changes.changed = connection.execute(
+                &format!(
+                    "
+                    UPDATE {mini} SET ({list}) = (
+                        SELECT {merged} FROM incoming n JOIN {mini} m ON m.MD5 = n.MD5
+                        WHERE n.MD5 = {mini}.MD5
+                    )
+                    WHERE MD5 IN (
+                        SELECT n.MD5 FROM incoming n JOIN {mini} m ON m.MD5 = n.MD5
+                        WHERE NOT ({same})
+                    )
+                    "
+                ),
+                [],
+            )?;
+            changes.added = connection.execute(
+                &format!(
+                    "
+                    INSERT INTO {mini} ({list}, MD5)
+                    SELECT {list}, MD5 FROM incoming n
+                    WHERE NOT EXISTS (SELECT 1 FROM {mini} m WHERE m.MD5 = n.MD5)
+                    "
+                ),
+                [],
+            )?;
+            if prune {
+                changes.removed = connection.execute(
+                    &format!("DELETE FROM {mini} WHERE MD5 NOT IN (SELECT MD5 FROM incoming)"),
+                    [],
+                )?;
+            }
+        } else {
+            changes.added =
+                connection.query_row(&format!("SELECT count(*) FROM {mini}"), [], |row| {
+                    row.get::<_, i64>(0)
+                })? as usize;
+        }
+        let fts: bool = connection.query_row(
+            "SELECT count(*) > 0 FROM sqlite_master WHERE name = ?1",
+            [format!("{mini}_fts")],
+            |row| row.get(0),
+        )?;
+        if fts && changes.added + changes.changed + changes.removed > 0 {
+            // the full-text index points at rows by rowid, so it has to be redone
+            connection.execute_batch(&format!(
+                "INSERT INTO {mini}_fts({mini}_fts) VALUES('rebuild');"
+            ))?;
+        }
+        if changes.added + changes.changed + changes.removed > 0 {
+            // the app builds the fuzzy index again from the new words
+            connection.execute_batch(&format!("DROP TABLE IF EXISTS {mini}_trigrams;"))?;
+        }
+        record_dump(connection, &changes, dump_date)?;
+        connection.execute_batch("DROP TABLE temp.incoming; COMMIT;")?;
+        applied.push(changes);
+    }
+    if staged_rows(connection, "topics")? > 0 {
+        connection.execute_batch(&format!(
+            "
+            DROP TABLE IF EXISTS topics;
+            ALTER TABLE {} RENAME TO topics;
+            ",
+            staging("topics")
+        ))?;
+    }
+    Ok(applied)
+}
+
+// One row for every dump applied to the catalog. The cached statistics are
+// out of date once anything is applied, so they go too.
+fn create_meta_table(connection: &Connection) -> rusqlite::Result<()> {
+    connection.execute_batch(
+        "
+        DROP TABLE IF EXISTS catalog_stats;
+        CREATE TABLE IF NOT EXISTS catalog_meta (
+            collection TEXT,
+            dump_date TEXT,
+            applied TEXT DEFAULT (datetime('now')),
+            added INTEGER,
+            changed INTEGER,
+            removed INTEGER
+        );
+        ",
+    )
+}
+
+fn record_dump(
+    connection: &Connection,
+    changes: &Changes,
+    dump_date: Option<&str>,
+) -> rusqlite::Result<()> {
+    connection.execute(
+        "INSERT INTO catalog_meta (collection, dump_date, added, changed, removed) VALUES (?1, ?2, ?3, ?4, ?5)",
+        rusqlite::params![
+            changes.table,
+            dump_date,
+            changes.added,
+            changes.changed,
+            changes.removed
+        ],
+    )?;
+    Ok(())
+}
+
+// where rows of a dump table are collected before compressing
+fn staging(table: &str) -> String {
+    format!("import_{}", table.to_lowercase())
+}
+
+// Returns the date from the dump's "Dump completed on" line, if it has one.
+fn load_dump<R: BufRead>(
+    reader: R,
+    connection: &Connection,
+    progress: &mut Progress,
+) -> Result<Option<String>, Box<dyn error::Error>> {
+    let mut statements = Statements::new(reader);
+    let mut stmt = Vec::new();
+    // column names of each table as declared in the dump
+    let mut declared: Vec<(String, Vec<String>)> = Vec::new();
+    let mut pending = 0;
+    connection.execute_batch("BEGIN")?;
+    while statements.next(&mut stmt)? {
+        match parse_statement(&stmt)? {
+            Statement::CreateTable { table, columns } => {
+                declared.retain(|(t, _)| t != &table);
+                declared.push((table, columns));
+            }
+            Statement::Insert {
+                table,
+                columns,
+                rows,
+            } => {
+                let keep = match TABLES.iter().find(|(t, _)| t.eq_ignore_ascii_case(&table)) {
+                    Some((_, keep)) => keep,
+                    None => continue,
+                };
+                let columns = match columns {
+                    Some(columns) => columns,
+                    None => declared
+                        .iter()
+                        .find(|(t, _)| t == &table)
+                        .map(|(_, c)| c.clone())
+                        .ok_or_else(|| format!("INSERT into {table} before CREATE TABLE"))?,
+                };
+                // position of each kept column in the dump's rows
+                let positions: Vec<Option<usize>> = keep
+                    .iter()
+                    .map(|k| columns.iter().position(|c| c.eq_ignore_ascii_case(k)))
+                    .collect();
+                let placeholders = vec!["?"; keep.len()].join(", ");
+                let mut insert = connection.prepare_cached(&format!(
+                    "INSERT INTO {} VALUES ({placeholders})",
+                    staging(&table)
+                ))?;
+                for row in rows {
+                    let values = positions
+                        .iter()
+                        .map(|p| p.and_then(|p| row.get(p).cloned()).unwrap_or(Value::Null));
+                    insert.execute(rusqlite::params_from_iter(values))?;
+                    progress.rows += 1;
+                    pending += 1;
+                }
+                if pending >= BATCH_ROWS {
+                    connection.execute_batch("COMMIT; BEGIN")?;
+                    pending = 0;
+                }
+            }
+            Statement::Other => {}
+        }
+        progress.report(statements.consumed);
+    }
+    connection.execute_batch("COMMIT")?;
+    Ok(statements.completed)
+}
+
+enum Statement<'a> {
+    CreateTable {
+        table: String,
+        columns: Vec<String>,
+    },
+    Insert {
+        table: String,
+        columns: Option<Vec<String>>,
+        rows: Rows<'a>,
+    },
+    Other,
+}
+
+fn parse_statement(stmt: &[u8]) -> Result<Statement<'_>, String> {
+    let mut p = Parser { s: stmt, i: 0 };
+    p.skip_whitespace();
+    if p.keyword("CREATE") && p.keyword("TABLE") {
+        let _ = p.keyword("IF") && p.keyword("NOT") && p.keyword("EXISTS");
+        let table = p.identifier()?;
+        p.skip_whitespace();
+        if !p.eat(b'(') {
+            return Ok(Statement::Other);
+        }
+        return Ok(Statement::CreateTable {
+            table,
+            columns: p.column_definitions(),
+        });
+    }
+    p.i = 0;
+    p.skip_whitespace();
+    if p.keyword("INSERT") {
+        let _ = p.keyword("IGNORE");
+        if !p.keyword("INTO") {
+            return Ok(Statement::Other);
+        }
+        let table = p.identifier()?;
+        p.skip_whitespace();
+        let mut columns = None;
+        if p.eat(b'(') {
+            let mut names = Vec::new();
+            loop {
+                names.push(p.identifier()?);
+                p.skip_whitespace();
+                if !p.eat(b',') {
+                    break;
+                }
+            }
+            p.skip_whitespace();
+            if !p.eat(b')') {
+                return Err(format!("bad column list in INSERT into {table}"));
+            }
+            columns = Some(names);
+        }
+        if !p.keyword("VALUES") {
+            return Ok(Statement::Other);
+        }
+        return Ok(Statement::Insert {
+            table,
+            columns,
+            rows: Rows { p },
+        });
+    }
+    Ok(Statement::Other)
+}
+
+// The tuples of an `INSERT ... VALUES (..), (..)`, parsed lazily so that rows
+// of tables we don't keep are never decoded.
+struct Rows<'a> {
+    p: Parser<'a>,
+}
+
+impl<'a> IntoIterator for Rows<'a> {
+    type Item = Vec<Value>;
+    type IntoIter = RowIter<'a>;
+    fn into_iter(self) -> Self::IntoIter {
+        RowIter {
+            p: self.p,
+            done: false,
+        }
+    }
+}
+
+struct RowIter<'a> {
+    p: Parser<'a>,
+    done: bool,
+}
+
+impl<'a> Iterator for RowIter<'a> {
+    type Item = Vec<Value>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let p = &mut self.p;
+        p.skip_whitespace();
+        if !p.eat(b'(') {
+            self.done = true;
+            return None;
+        }
+        let mut row = Vec::new();
+        loop {
+            p.skip_whitespace();
+            match p.value() {
+                Some(v) => row.push(v),
+                None => {
+                    log::warn!("Unparseable value near byte {} of INSERT", p.i);
+                    self.done = true;
+                    return None;
+                }
+            }
+            p.skip_whitespace();
+            if p.eat(b',') {
+                continue;
+            }
+            if p.eat(b')') {
+                break;
+            }
+            self.done = true;
+            return None;
+        }
+        p.skip_whitespace();
+        if !p.eat(b',') {
+            self.done = true;
+        }
+        Some(row)
+    }
+}
+
+struct Parser<'a> {
+    s: &'a [u8],
+    i: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.s.get(self.i).copied()
+    }
+
+    fn eat(&mut self, c: u8) -> bool {
+        if self.peek() == Some(c) {
+            self.i += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(c) if c.is_ascii_whitespace()) {
+            self.i += 1;
+        }
+    }
+
+    // case-insensitive keyword followed by whitespace or punctuation
+    fn keyword(&mut self, word: &str) -> bool {
+        self.skip_whitespace();
+        let end = self.i + word.len();
+        if end > self.s.len() || !self.s[self.i..end].eq_ignore_ascii_case(word.as_bytes()) {
+            return false;
+        }
+        if matches!(self.s.get(end), Some(c) if c.is_ascii_alphanumeric() || *c == b'_') {
+            return false;
+        }
+        self.i = end;
+        true
+    }
+
+    fn identifier(&mut self) -> Result<String, String> {
+        self.skip_whitespace();
+        let start = self.i;
+        if self.eat(b'`') {
+            while let Some(c) = self.peek() {
+                self.i += 1;
+                if c == b'`' {
+                    return Ok(String::from_utf8_lossy(&self.s[start + 1..self.i - 1]).into());
+                }
+            }
+            return Err("unterminated identifier".to_string());
+        }
+        while matches!(self.peek(), Some(c) if c.is_ascii_alphanumeric() || c == b'_' || c == b'$')
+        {
+            self.i += 1;
+        }
+        if self.i == start {
+            return Err(format!("expected identifier at byte {start}"));
+        }
+        Ok(String::from_utf8_lossy(&self.s[start..self.i]).into())
+    }
+
+    // Names of the columns in the body of a CREATE TABLE, skipping keys and
+    // constraints, which don't start with a quoted name.
+    fn column_definitions(&mut self) -> Vec<String> {
+        let mut columns = Vec::new();
+        let mut depth = 0;
+        let mut start_of_definition = true;
+        while let Some(c) = self.peek() {
+            if start_of_definition {
+                self.skip_whitespace();
+                if self.peek() == Some(b'`') {
+                    if let Ok(name) = self.identifier() {
+                        columns.push(name);
+                    }
+                }
+                start_of_definition = false;
+                continue;
+            }
+            match c {
+                b'\'' | b'"' => {
+                    self.string();
+                    continue;
+                }
+                b'(' => depth += 1,
+                b')' if depth == 0 => break,
+                b')' => depth -= 1,
+                b',' if depth == 0 => start_of_definition = true,
+                _ => {}
+            }
+            self.i += 1;
+        }
+        columns
+    }
+
+    fn value(&mut self) -> Option<Value> {
+        match self.peek()? {
+            b'\'' | b'"' => self.string().map(Value::Text),
+            // character set introducer, e.g. _binary '...' or _utf8mb4'...'
+            b'_' => {
+                self.identifier().ok()?;
+                self.skip_whitespace();
+                self.string().map(Value::Text)
+            }
+            _ if self.keyword("NULL") => Some(Value::Null),
+            _ => {
+                let start = self.i;
+                while matches!(self.peek(), Some(c) if c.is_ascii_alphanumeric() || b"+-.".contains(&c))
+                {
+                    self.i += 1;
+                }
+                let text = std::str::from_utf8(&self.s[start..self.i]).ok()?;
+                if text.is_empty() {
+                    None
+                } else if let Ok(n) = text.parse::<i64>() {
+                    Some(Value::Integer(n))
+                } else if let Ok(n) = text.parse::<f64>() {
+                    Some(Value::Real(n))
+                } else {
+                    // hex literals and anything else we don't understand
+                    Some(Value::Text(text.to_string()))
+                }
+            }
+        }
+    }
+
+    // a quoted string with MySQL backslash escapes
+    fn string(&mut self) -> Option<String> {
+        let quote = self.peek()?;
+        self.i += 1;
+        let mut out = Vec::new();
+        loop {
+            let c = self.peek()?;
+            self.i += 1;
+            match c {
+                b'\\' => {
+                    let e = self.peek()?;
+                    self.i += 1;
+                    match e {
+                        b'0' => out.push(0),
+                        b'n' => out.push(b'\n'),
+                        b'r' => out.push(b'\r'),
+                        b't' => out.push(b'\t'),
+                        b'b' => out.push(8),
+                        b'Z' => out.push(26),
+                        // these keep their backslash, they're only special in LIKE patterns
+                        b'%' | b'_' => out.extend_from_slice(&[b'\\', e]),
+                        e => out.push(e),
+                    }
+                }
+                c if c == quote => {
+                    // a doubled quote is a literal quote
+                    if self.eat(quote) {
+                        out.push(quote);
+                    } else {
+                        break;
+                    }
+                }
+                c => out.push(c),
+            }
+        }
+        Some(match String::from_utf8(out) {
+            Ok(s) => s,
+            Err(e) => String::from_utf8_lossy(e.as_bytes()).into_owned(),
+        })
+    }
+}
+
+// Splits a dump into statements on `;`, ignoring semicolons inside quotes and
+// dropping comments (including the `/*!40101 ... */` conditional ones).
+struct Statements<R> {
+    reader: R,
+    consumed: u64,
+    state: State,
+    // the line comment being skipped
+    comment: Vec<u8>,
+    // when mysqldump finished, from its last comment
+    completed: Option<String>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Normal,
+    Quoted(u8),
+    Escaped(u8),
+    MaybeComment(u8),
+    LineComment,
+    BlockComment,
+    BlockCommentEnd,
+}
+
+impl<R: BufRead> Statements<R> {
+    fn new(reader: R) -> Self {
+        Self {
+            reader,
+            consumed: 0,
+            state: State::Normal,
+            comment: Vec::new(),
+            completed: None,
+        }
+    }
+
+    // Read the next statement into `stmt`, without its `;`.
+    // Returns false at the end of the input.
+    fn next(&mut self, stmt: &mut Vec<u8>) -> io::Result<bool> {
+        stmt.clear();
+        loop {
+            let buf = self.reader.fill_buf()?;
+            if buf.is_empty() {
+                return Ok(!stmt.iter().all(u8::is_ascii_whitespace));
+            }
+            let mut used = 0;
+            let mut complete = false;
+            for &c in buf {
+                used += 1;
+                self.state = match (self.state, c) {
+                    (State::Normal, b';') => {
+                        complete = true;
+                        State::Normal
+                    }
+                    (State::Normal, b'\'' | b'"' | b'`') => {
+                        stmt.push(c);
+                        State::Quoted(c)
+                    }
+                    (State::Normal, b'-' | b'/') => State::MaybeComment(c),
+                    (State::Normal, b'#') => State::LineComment,
+                    (State::Normal, c) => {
+                        stmt.push(c);
+                        State::Normal
+                    }
+                    (State::MaybeComment(b'-'), b'-') => State::LineComment,
+                    (State::MaybeComment(b'/'), b'*') => State::BlockComment,
+                    (State::MaybeComment(first), c) => {
+                        stmt.push(first);
+                        if c == b';' {
+                            complete = true;
+                            State::Normal
+                        } else {
+                            stmt.push(c);
+                            match c {
+                                b'\'' | b'"' | b'`' => State::Quoted(c),
+                                _ => State::Normal,
+                            }
+                        }
+                    }
+                    (State::Quoted(q), b'\\') if q != b'`' => {
+                        stmt.push(c);
+                        State::Escaped(q)
+                    }
+                    (State::Quoted(q), c) => {
+                        stmt.push(c);
+                        if c == q {
+                            State::Normal
+                        } else {
+                            State::Quoted(q)
+                        }
+                    }
+                    (State::Escaped(q), c) => {
+                        stmt.push(c);
+                        State::Quoted(q)
+                    }
+                    (State::LineComment, b'\n') => {
+                        let comment = String::from_utf8_lossy(&self.comment);
+                        if let Some(date) = comment.trim().strip_prefix("Dump completed on") {
+                            self.completed = Some(date.trim().to_string());
+                        }
+                        self.comment.clear();
+                        stmt.push(b'\n');
+                        State::Normal
+                    }
+                    (State::LineComment, c) => {
+                        self.comment.push(c);
+                        State::LineComment
+                    }
+                    (State::BlockComment | State::BlockCommentEnd, b'*') => State::BlockCommentEnd,
+                    (State::BlockCommentEnd, b'/') => {
+                        stmt.push(b' ');
+                        State::Normal
+                    }
+                    (State::BlockComment | State::BlockCommentEnd, _) => State::BlockComment,
+                };
+                if complete {
+                    break;
+                }
+            }
+            self.reader.consume(used);
+            self.consumed += used as u64;
+            if complete {
+                if stmt.iter().all(u8::is_ascii_whitespace) {
+                    // empty statement, e.g. the second `;` of `;;`
+                    stmt.clear();
+                    continue;
+                }
+                return Ok(true);
+            }
+        }
+    }
+}
+
+struct Progress {
+    total: u64,
+    rows: u64,
+    started: Instant,
+    reported: Instant,
+}
+
+impl Progress {
+    fn new(total: u64) -> Self {
+        Self {
+            total,
+            rows: 0,
+            started: Instant::now(),
+            reported: Instant::now(),
+        }
+    }
+
+    fn report(&mut self, done: u64) {
+        if self.reported.elapsed() < Duration::from_secs(1) {
+            return;
+        }
+        self.reported = Instant::now();
+        let percent = 100.0 * done as f64 / self.total.max(1) as f64;
+        let mib = done as f64 / 1024.0 / 1024.0;
+        let rate = mib / self.started.elapsed().as_secs_f64().max(0.001);
+        eprint!(
+            "\r{:5.1}%  {:.0} MiB  {:.1} MiB/s  {} rows kept   ",
+            percent, mib, rate, self.rows
+        );
+        let _ = io::stderr().flush();
+    }
+
+    fn finish(&self) {
+        eprintln!(
+            "\r100.0%  {} rows kept in {:.0}s          ",
+            self.rows,
+            self.started.elapsed().as_secs_f64()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DUMP: &str = r#"
+-- MySQL dump 10.13
+/*!40101 SET NAMES utf8 */;
+DROP TABLE IF EXISTS `fiction`;
+CREATE TABLE `fiction` (
+  `ID` int(10) unsigned NOT NULL AUTO_INCREMENT,
+  `MD5` char(32) CHARACTER SET ascii DEFAULT NULL,
+  `Title` varchar(2000) DEFAULT '',
+  `Author` varchar(300) DEFAULT '',
+  `Series` varchar(300) DEFAULT '',
+  `Language` varchar(45) DEFAULT '',
+  `Extension` varchar(10) DEFAULT '',
+  `Filesize` int(10) unsigned DEFAULT NULL,
+  PRIMARY KEY (`ID`),
+  KEY `MD5` (`MD5`)
+) ENGINE=MyISAM;
+INSERT INTO `fiction` VALUES (1,'AAAA','Guards! Guards!','Pratchett, Terry','Discworld; 8','English','epub',12345),(2,'BBBB','It\'s a \"test\"; really','Nobody','',NULL,'pdf',1);
+DELIMITER ;;
+/*!50003 CREATE*/ /*!50003 TRIGGER `x` BEFORE UPDATE ON `fiction` FOR EACH ROW BEGIN INSERT INTO `y` SET `a` = 1; END */;;
+DELIMITER ;
+CREATE TABLE `fiction_hashes` (`md5` char(32), `crc32` char(8), `ipfs_cid` char(62));
+INSERT INTO `fiction_hashes` VALUES ('aaaa','x','bafyfirst'),('bbbb','y','');
+-- Dump completed on 2023-09-20 10:00:00
+"#;
+
+    // a later dump: AAAA retitled, BBBB now has a CID, CCCC is new
+    const UPDATE: &str = r#"
+CREATE TABLE `fiction` (`ID` int, `MD5` char(32), `Title` varchar(2000), `Author` varchar(300), `Filesize` int);
+INSERT INTO `fiction` VALUES (1,'AAAA','Guards! Guards! (2nd ed)','Pratchett, Terry',12345),(2,'BBBB','Second','Nobody',1),(3,'CCCC','Mort','Pratchett, Terry',5);
+CREATE TABLE `fiction_hashes` (`md5` char(32), `ipfs_cid` char(62));
+INSERT INTO `fiction_hashes` VALUES ('aaaa','bafyfirst'),('bbbb','bafysecond'),('cccc','bafythird');
+-- Dump completed on 2023-10-01 10:00:00
+"#;
+
+    #[test]
+    fn test_parse_rows() {
+        let stmt = br#"INSERT INTO `t` VALUES (1,'a\'b',NULL,-2.5),(2,'c''d',_binary 'e',0x1F)"#;
+        let (table, rows) = match parse_statement(stmt) {
+            Ok(Statement::Insert { table, rows, .. }) => (table, rows),
+            _ => panic!("not an insert"),
+        };
+        assert_eq!(table, "t");
+        let rows: Vec<Vec<Value>> = rows.into_iter().collect();
+        assert_eq!(
+            rows,
+            vec![
+                vec![
+                    Value::Integer(1),
+                    Value::Text("a'b".to_string()),
+                    Value::Null,
+                    Value::Real(-2.5)
+                ],
+                vec![
+                    Value::Integer(2),
+                    Value::Text("c'd".to_string()),
+                    Value::Text("e".to_string()),
+                    Value::Text("0x1F".to_string())
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_load_dump() {
+        let connection = Connection::open_in_memory().unwrap();
+        for (table, columns) in TABLES {
+            create_staging_table(&connection, table, columns).unwrap();
+        }
+        let mut progress = Progress::new(DUMP.len() as u64);
+        let date = load_dump(DUMP.as_bytes(), &connection, &mut progress).unwrap();
+        assert_eq!(progress.rows, 4);
+        assert_eq!(date.as_deref(), Some("2023-09-20 10:00:00"));
+        compress(&connection, date.as_deref()).unwrap();
+
+        let rows: Vec<(String, String, String, Option<String>, i64)> = connection
+            .prepare("SELECT ipfs_cid, Title, Series, Year, Filesize FROM fiction_mini")
+            .unwrap()
+            .query_map([], |r| {
+                Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?, r.get(4)?))
+            })
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(
+            rows,
+            vec![(
+                "bafyfirst".to_string(),
+                "Guards! Guards!".to_string(),
+                "Discworld; 8".to_string(),
+                None,
+                12345
+            )]
+        );
+        // no non-fiction in this dump
+        let tables: i64 = connection
+            .query_row(
+                "SELECT count(*) FROM sqlite_master WHERE name = 'non_fiction_mini'",
+                [],
+                |r| r.get(0),
+            )
+            .unwrap();
+        assert_eq!(tables, 0);
+    }
+
+    #[test]
+    fn test_update() {
+        let connection = Connection::open_in_memory().unwrap();
+        let load = |dump: &str| {
+            for (table, columns) in TABLES {
+                create_staging_table(&connection, table, columns).unwrap();
+            }
+            let mut progress = Progress::new(dump.len() as u64);
+            load_dump(dump.as_bytes(), &connection, &mut progress).unwrap()
+        };
+        let date = load(DUMP);
+        compress(&connection, date.as_deref()).unwrap();
+        connection
+            .execute_batch(
+                "
+                CREATE VIRTUAL TABLE fiction_mini_fts USING fts5(Title, Author, Series, Publisher, content='fiction_mini', content_rowid='rowid');
+                INSERT INTO fiction_mini_fts(fiction_mini_fts) VALUES('rebuild');
+                ",
+            )
+            .unwrap();
+
+        let date = load(UPDATE);
+        let changes = apply_update(&connection, date.as_deref(), false).unwrap();
+        assert_eq!(
+            changes,
+            vec![Changes {
+                table: "fiction_mini",
+                added: 2,
+                changed: 1,
+                removed: 0
+            }]
+        );
+        // nothing differs the second time round
+        load(UPDATE);
+        let changes = apply_update(&connection, date.as_deref(), false).unwrap();
+        assert_eq!(changes[0].added + changes[0].changed, 0);
+
+        let titles: Vec<String> = connection
+            .prepare("SELECT Title FROM fiction_mini ORDER BY MD5")
+            .unwrap()
+            .query_map([], |r| r.get(0))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(titles, vec!["Guards! Guards! (2nd ed)", "Second", "Mort"]);
+        // the update dump has no series, language or extension, so those stay
+        let kept: (String, String, String) = connection
+            .query_row(
+                "SELECT Series, Language, Extension FROM fiction_mini WHERE MD5 = 'aaaa'",
+                [],
+                |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!(
+            kept,
+            (
+                "Discworld; 8".to_string(),
+                "English".to_string(),
+                "epub".to_string()
+            )
+        );
+        // the index was rebuilt to match
+        let found: i64 = connection
+            .query_row(
+                "SELECT count(*) FROM fiction_mini_fts WHERE fiction_mini_fts MATCH 'mort'",
+                [],
+                |r| r.get(0),
+            )
+            .unwrap();
+        assert_eq!(found, 1);
+
+        // a full dump with only CCCC in it
+        load(
+            "INSERT INTO `fiction` (`MD5`, `Title`) VALUES ('CCCC', 'Mort');
+            INSERT INTO `fiction_hashes` (`md5`, `ipfs_cid`) VALUES ('cccc', 'bafythird');",
+        );
+        let changes = apply_update(&connection, None, true).unwrap();
+        assert_eq!((changes[0].changed, changes[0].removed), (0, 2));
+        let author: String = connection
+            .query_row("SELECT Author FROM fiction_mini", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(author, "Pratchett, Terry");
+
+        let dates: Vec<Option<String>> = connection
+            .prepare("SELECT dump_date FROM catalog_meta ORDER BY rowid")
+            .unwrap()
+            .query_map([], |r| r.get(0))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(
+            dates,
+            vec![
+                Some("2023-09-20 10:00:00".to_string()),
+                Some("2023-10-01 10:00:00".to_string()),
+                Some("2023-10-01 10:00:00".to_string()),
+                None
+            ]
+        );
+    }
+}
@@ -1,8 +1,18 @@
 #![warn(clippy::all, rust_2018_idioms)]
 
 mod app;
+mod authors;
 mod db;
 mod download;
+pub mod import;
 pub use app::TemplateApp;
+mod config;
+mod facets;
+mod fold;
+mod fuzzy;
+mod search;
+mod series;
+mod stats;
+mod topics;
 mod uifilter;
-mod config;
\ No newline at end of file
+mod verify;
@@ -13,6 +13,16 @@ fn main() {
 
     tracing_subscriber::fmt::init();
 
+    // `rlgdesktop import dump.sql` builds the catalog instead of opening the window
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("import") {
+        if let Err(e) = rlgdesktop::import::run(&args[1..]) {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let native_options = eframe::NativeOptions {
         icon_data: load_icon(PathBuf::from(".").join("assets").join("icon-256.png")),
         initial_window_pos: Some(egui::Pos2::new(100.0, 100.0)),
@@ -0,0 +1,472 @@
+// The omnibox query language, e.g.
+//
+//     author:pratchett series:discworld lang:english -format:pdf year:>=1990 "guards guards"
+//
+// Words without a prefix have to appear in the title, authors or series.
+// Quotes keep a phrase together, a leading `-` excludes matches. Year and size
+// take ranges: `year:1990..1999`, `size:<5mb` (sizes without a unit are MB).
+// Slashes make a box's text a regular expression: `title:/^guards/`.
+
+use crate::db::Params;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub enum Field {
+    Title,
+    Authors,
+    Series,
+    Language,
+    Format,
+    Publisher,
+    Year,
+    Size,
+}
+
+impl Field {
+    fn from_prefix(prefix: &str) -> Option<Field> {
+        Some(match prefix.to_lowercase().as_str() {
+            "title" => Field::Title,
+            "author" | "authors" | "by" => Field::Authors,
+            "series" => Field::Series,
+            "lang" | "language" => Field::Language,
+            "format" | "ext" => Field::Format,
+            "publisher" | "pub" => Field::Publisher,
+            "year" => Field::Year,
+            "size" => Field::Size,
+            _ => return None,
+        })
+    }
+
+    fn prefix(&self) -> &'static str {
+        match self {
+            Field::Title => "title",
+            Field::Authors => "author",
+            Field::Series => "series",
+            Field::Language => "lang",
+            Field::Format => "format",
+            Field::Publisher => "publisher",
+            Field::Year => "year",
+            Field::Size => "size",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct Term {
+    // None for title, authors or series
+    pub field: Option<Field>,
+    pub text: String,
+    pub negated: bool,
+    // quoted, so match the words together rather than one by one
+    pub phrase: bool,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ParseError {
+    pub message: String,
+    // byte offset into the query
+    pub position: usize,
+}
+
+pub fn parse(query: &str) -> Result<Vec<Term>, ParseError> {
+    let mut terms = Vec::new();
+    let chars: Vec<(usize, char)> = query.char_indices().collect();
+    let mut i = 0;
+    let at = |i: usize| chars.get(i).map(|(p, _)| *p).unwrap_or(query.len());
+    while i < chars.len() {
+        if chars[i].1.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        let negated = chars[i].1 == '-';
+        if negated {
+            i += 1;
+        }
+        // a field prefix is letters up to a colon; any other word with a
+        // colon in it, like "Dune: Messiah", is just text
+        let mut field = None;
+        let mut j = i;
+        while j < chars.len() && chars[j].1.is_alphabetic() {
+            j += 1;
+        }
+        if j > i && j < chars.len() && chars[j].1 == ':' {
+            let prefix: String = chars[i..j].iter().map(|(_, c)| c).collect();
+            field = Field::from_prefix(&prefix);
+            if field.is_some() {
+                i = j + 1;
+            }
+        }
+        let phrase = i < chars.len() && chars[i].1 == '"';
+        let mut text = String::new();
+        if phrase {
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    None => {
+                        return Err(ParseError {
+                            message: "missing closing quote".to_string(),
+                            position: at(start),
+                        })
+                    }
+                    Some((_, '"')) => {
+                        i += 1;
+                        break;
+                    }
+                    Some((_, '\\')) if matches!(chars.get(i + 1), Some((_, '"' | '\\'))) => {
+                        text.push(chars[i + 1].1);
+                        i += 2;
+                    }
+                    Some((_, c)) => {
+                        text.push(*c);
+                        i += 1;
+                    }
+                }
+            }
+        } else {
+            while i < chars.len() && !chars[i].1.is_whitespace() {
+                text.push(chars[i].1);
+                i += 1;
+            }
+        }
+        if text.trim().is_empty() {
+            return Err(ParseError {
+                message: "nothing to search for".to_string(),
+                position: at(start),
+            });
+        }
+        terms.push(Term {
+            field,
+            text,
+            negated,
+            phrase,
+        });
+    }
+    Ok(terms)
+}
+
+// Put the parsed terms into the search. The first plain term for each of the
+// advanced view's boxes goes into that box, everything else is kept as a term.
+pub fn apply(terms: Vec<Term>, params: &mut Params) {
+    params.regex.clear();
+    params.title.clear();
+    params.authors.clear();
+    params.series.clear();
+    params.language.clear();
+    params.format.clear();
+    params.terms.clear();
+    params.min_year = None;
+    params.max_year = None;
+    params.min_size = None;
+    params.max_size = None;
+    for term in terms {
+        let range = match (term.negated, term.field) {
+            (false, Some(Field::Year)) => parse_range(&term.text, |y| y.trim().parse().ok())
+                .map(|r| (r, &mut params.min_year, &mut params.max_year)),
+            (false, Some(Field::Size)) => parse_range(&term.text, parse_size)
+                .map(|r| (r, &mut params.min_size, &mut params.max_size)),
+            _ => None,
+        };
+        if let Some(((low, high), min, max)) = range {
+            // repeated ranges narrow each other down
+            *min = low.max(*min);
+            *max = match (high, *max) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            };
+            continue;
+        }
+        if let (false, Some(field)) = (term.negated, term.field) {
+            let pattern = as_pattern(&term.text);
+            if let Some(text) = field_box(params, field).filter(|text| text.is_empty()) {
+                *text = pattern.unwrap_or(&term.text).to_string();
+                if pattern.is_some() {
+                    params.regex.push(field);
+                }
+                continue;
+            }
+        }
+        params.terms.push(term);
+    }
+}
+
+// the pattern in `/pattern/`
+fn as_pattern(text: &str) -> Option<&str> {
+    let pattern = text.strip_prefix('/')?.strip_suffix('/')?;
+    (!pattern.is_empty()).then_some(pattern)
+}
+
+// The omnibox text for a search, so editing the boxes updates it too.
+pub fn to_query(params: &Params) -> String {
+    let boxes = [
+        (Field::Title, &params.title),
+        (Field::Authors, &params.authors),
+        (Field::Series, &params.series),
+        (Field::Language, &params.language),
+        (Field::Format, &params.format),
+    ];
+    let mut parts: Vec<String> = boxes
+        .into_iter()
+        .filter(|(_, text)| !text.trim().is_empty())
+        .map(|(field, text)| match params.regex.contains(&field) {
+            true => format_term(Some(field), &format!("/{}/", text.trim()), false, false),
+            false => format_term(Some(field), text, false, false),
+        })
+        .collect();
+    for term in &params.terms {
+        parts.push(format_term(
+            term.field,
+            &term.text,
+            term.negated,
+            term.phrase,
+        ));
+    }
+    let ranges = [
+        (
+            Field::Year,
+            params.min_year,
+            params.max_year,
+            format_year as fn(_) -> _,
+        ),
+        (Field::Size, params.min_size, params.max_size, format_size),
+    ];
+    for (field, min, max, format) in ranges {
+        let range = match (min, max) {
+            (Some(min), Some(max)) if min == max => format(min),
+            (Some(min), Some(max)) => format!("{}..{}", format(min), format(max)),
+            (Some(min), None) => format!(">={}", format(min)),
+            // `size:<5mb` reads better than `size:<=5242879b`
+            (None, Some(max)) if format(max.saturating_add(1)).len() < format(max).len() => {
+                format!("<{}", format(max.saturating_add(1)))
+            }
+            (None, Some(max)) => format!("<={}", format(max)),
+            (None, None) => continue,
+        };
+        parts.push(format!("{}:{}", field.prefix(), range));
+    }
+    parts.join(" ")
+}
+
+// `>=1990`, `<1990`, `1990..1999` or just `1990`, as inclusive bounds
+fn parse_range(text: &str, value: fn(&str) -> Option<i64>) -> Option<(Option<i64>, Option<i64>)> {
+    if let Some((low, high)) = text.split_once("..") {
+        let low = if low.is_empty() {
+            None
+        } else {
+            Some(value(low)?)
+        };
+        let high = if high.is_empty() {
+            None
+        } else {
+            Some(value(high)?)
+        };
+        return Some((low, high));
+    }
+    for op in [">=", "<=", ">", "<", "="] {
+        if let Some(rest) = text.strip_prefix(op) {
+            let v = value(rest)?;
+            return Some(match op {
+                ">=" => (Some(v), None),
+                ">" => (Some(v.checked_add(1)?), None),
+                "<=" => (None, Some(v)),
+                "<" => (None, Some(v.checked_sub(1)?)),
+                _ => (Some(v), Some(v)),
+            });
+        }
+    }
+    let v = value(text)?;
+    Some((Some(v), Some(v)))
+}
+
+// `5mb`, `700kb`, `1.5gb` or `12b`; a bare number is MB
+pub fn parse_size(text: &str) -> Option<i64> {
+    let text = text.trim().to_lowercase();
+    let split = text
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: f64 = number.parse().ok()?;
+    let scale = match unit.trim() {
+        "b" => 1.0,
+        "k" | "kb" | "kib" => 1024.0,
+        "" | "m" | "mb" | "mib" => 1024.0 * 1024.0,
+        "g" | "gb" | "gib" => 1024.0 * 1024.0 * 1024.0,
+        _ => return None,
+    };
+    Some((number * scale).round() as i64)
+}
+
+fn format_year(year: i64) -> String {
+    year.to_string()
+}
+
+fn format_size(bytes: i64) -> String {
+    for (unit, scale) in [("gb", 1 << 30), ("mb", 1 << 20), ("kb", 1 << 10)] {
+        if bytes % scale == 0 && bytes != 0 {
+            return format!("{}{}", bytes / scale, unit);
+        }
+    }
+    format!("{}b", bytes)
+}
+
+fn field_box(params: &mut Params, field: Field) -> Option<&mut String> {
+    match field {
+        Field::Title => Some(&mut params.title),
+        Field::Authors => Some(&mut params.authors),
+        Field::Series => Some(&mut params.series),
+        Field::Language => Some(&mut params.language),
+        Field::Format => Some(&mut params.format),
+        Field::Publisher | Field::Year | Field::Size => None,
+    }
+}
+
+fn format_term(field: Option<Field>, text: &str, negated: bool, phrase: bool) -> String {
+    let text = text.trim();
+    let mut out = String::new();
+    if negated {
+        out.push('-');
+    }
+    if let Some(field) = field {
+        out.push_str(field.prefix());
+        out.push(':');
+    }
+    // quote anything that wouldn't read back as the same single term
+    let needs_quotes = phrase
+        || text.contains(char::is_whitespace)
+        || text.contains('"')
+        || (field.is_none() && (text.starts_with('-') || text.contains(':')));
+    if needs_quotes {
+        out.push('"');
+        out.push_str(&text.replace('\\', "\\\\").replace('"', "\\\""));
+        out.push('"');
+    } else {
+        out.push_str(text);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let terms = parse(
+            r#"author:pratchett series:discworld lang:english -format:pdf year:>=1990 "guards guards""#,
+        )
+        .unwrap();
+        let summary: Vec<(Option<Field>, &str, bool, bool)> = terms
+            .iter()
+            .map(|t| (t.field, t.text.as_str(), t.negated, t.phrase))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (Some(Field::Authors), "pratchett", false, false),
+                (Some(Field::Series), "discworld", false, false),
+                (Some(Field::Language), "english", false, false),
+                (Some(Field::Format), "pdf", true, false),
+                (Some(Field::Year), ">=1990", false, false),
+                (None, "guards guards", false, true),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(parse(r#"title:"guards"#).unwrap_err().position, 0);
+        assert_eq!(parse("mort -").unwrap_err().position, 5);
+        assert_eq!(
+            parse(r#""dune: messiah""#).unwrap()[0].text,
+            "dune: messiah"
+        );
+    }
+
+    #[test]
+    fn test_unknown_prefix() {
+        let texts = |query: &str| -> Vec<(Option<Field>, String)> {
+            parse(query)
+                .unwrap()
+                .into_iter()
+                .map(|t| (t.field, t.text))
+                .collect()
+        };
+        assert_eq!(
+            texts("Dune: Messiah"),
+            vec![(None, "Dune:".to_string()), (None, "Messiah".to_string())]
+        );
+        assert_eq!(
+            texts("-isbn:123 by:herbert"),
+            vec![
+                (None, "isbn:123".to_string()),
+                (Some(Field::Authors), "herbert".to_string())
+            ]
+        );
+        let mut params = Params::default();
+        apply(parse("Dune: Messiah").unwrap(), &mut params);
+        assert_eq!(to_query(&params), r#""Dune:" Messiah"#);
+    }
+
+    #[test]
+    fn test_ranges() {
+        let mut params = Params::default();
+        apply(
+            parse("year:>=1990 year:<2000 size:<5mb size:700kb..").unwrap(),
+            &mut params,
+        );
+        assert_eq!((params.min_year, params.max_year), (Some(1990), Some(1999)));
+        assert_eq!(
+            (params.min_size, params.max_size),
+            (Some(700 * 1024), Some(5 * 1024 * 1024 - 1))
+        );
+        assert!(params.terms.is_empty());
+        assert_eq!(to_query(&params), "year:1990..1999 size:700kb..5242879b");
+        apply(parse("size:<5mb").unwrap(), &mut params);
+        assert_eq!(to_query(&params), "size:<5mb");
+        apply(parse("year:1995 size:<=5").unwrap(), &mut params);
+        assert_eq!(to_query(&params), "year:1995 size:<=5mb");
+        // too big to add one to, so not a range
+        apply(parse("size:>99999999999gb").unwrap(), &mut params);
+        assert_eq!(params.min_size, None);
+        assert_eq!(params.terms.len(), 1);
+        params.terms.clear();
+        params.max_size = Some(i64::MAX);
+        assert_eq!(to_query(&params), format!("size:<={}b", i64::MAX));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let query = r#"title:"guards guards" author:pratchett -format:pdf "night watch" -mort"#;
+        let mut params = Params::default();
+        apply(parse(query).unwrap(), &mut params);
+        assert_eq!(params.title, "guards guards");
+        assert_eq!(params.authors, "pratchett");
+        assert_eq!(params.terms.len(), 3);
+        assert_eq!(to_query(&params), query);
+    }
+
+    #[test]
+    fn test_patterns() {
+        let mut params = Params {
+            title: "^guards".to_string(),
+            series: "disc world".to_string(),
+            regex: vec![Field::Title, Field::Series],
+            ..Default::default()
+        };
+        let query = to_query(&params);
+        assert_eq!(query, r#"title:/^guards/ series:"/disc world/""#);
+        apply(parse(&query).unwrap(), &mut params);
+        assert_eq!(params.title, "^guards");
+        assert_eq!(params.series, "disc world");
+        assert_eq!(params.regex, vec![Field::Title, Field::Series]);
+        // the boxes the omnibox fills are plain text unless it says otherwise
+        apply(
+            parse("title:guards author:/^pratchett/").unwrap(),
+            &mut params,
+        );
+        assert_eq!(params.title, "guards");
+        assert_eq!(params.regex, vec![Field::Authors]);
+        apply(parse("title:/ //").unwrap(), &mut params);
+        assert_eq!(params.title, "/");
+        assert!(params.regex.is_empty());
+    }
+}
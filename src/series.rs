@@ -0,0 +1,293 @@
+use std::{cmp::Ordering, collections::HashMap};
+
+use crate::{
+    app::{download_button, is_downloaded, queue_download},
+    db::{parse_series, BookRef},
+    download,
+};
+
+// The books of one series found in the results, a volume at a time
+#[derive(Debug)]
+pub struct Series {
+    pub name: String,
+    pub volumes: Vec<Volume>,
+    // runs of volumes that aren't in the results, worked out once
+    pub gaps: Vec<(i64, i64)>,
+}
+
+// Every edition of one volume: other formats, uploads and translations
+#[derive(Debug)]
+pub struct Volume {
+    pub number: Option<f64>,
+    pub editions: Vec<BookRef>,
+}
+
+impl Volume {
+    // whether the volume is on disk, queued or missing, and the edition to
+    // show for it: the one on disk, or else the one to download
+    fn state(&self) -> (&'static str, &BookRef) {
+        let mut queued = false;
+        for book in &self.editions {
+            if is_downloaded(book) {
+                return ("have", book);
+            }
+            if let Ok(status) = book.download_status.read() {
+                queued |= status.as_str() == "Queued" || status.as_str() == "Downloading";
+            }
+        }
+        match queued {
+            true => ("queued", &self.editions[0]),
+            false => ("missing", &self.editions[0]),
+        }
+    }
+}
+
+// The results grouped into series, kept until the results change
+#[derive(Default)]
+pub struct SeriesView {
+    grouped: Option<Vec<Series>>,
+}
+
+impl SeriesView {
+    // group the results again the next time they're shown
+    pub fn clear(&mut self) {
+        self.grouped = None;
+    }
+}
+
+// Books with a series, grouped by series name and then by volume. Volumes are
+// in number order with the unnumbered ones last, and editions keep the order
+// they were found in.
+pub fn group(books: &[BookRef]) -> Vec<Series> {
+    let mut index: HashMap<String, usize> = HashMap::new();
+    let mut series: Vec<Series> = Vec::new();
+    for book in books {
+        let (name, number) = parse_series(&book.series);
+        if name.is_empty() {
+            continue;
+        }
+        let i = *index.entry(name.to_lowercase()).or_insert_with(|| {
+            series.push(Series {
+                name: name.to_string(),
+                volumes: vec![],
+                gaps: vec![],
+            });
+            series.len() - 1
+        });
+        // books without a number are told apart by title
+        let same = |volume: &Volume| match number {
+            Some(_) => volume.number == number,
+            None => {
+                volume.number.is_none()
+                    && volume.editions[0].title.to_lowercase() == book.title.to_lowercase()
+            }
+        };
+        let volumes = &mut series[i].volumes;
+        match volumes.iter_mut().find(|volume| same(volume)) {
+            Some(volume) => volume.editions.push(book.clone()),
+            None => volumes.push(Volume {
+                number,
+                editions: vec![book.clone()],
+            }),
+        }
+    }
+    for series in &mut series {
+        series.volumes.sort_by(|a, b| match (a.number, b.number) {
+            (Some(a), Some(b)) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+            (a, b) => b.is_some().cmp(&a.is_some()),
+        });
+        series.gaps = gaps(&series.volumes);
+    }
+    series.sort_by_key(|series| series.name.to_lowercase());
+    series
+}
+
+// a bigger number at the end of a series is a year, an ISBN or part of the
+// name, like "Warhammer 40000"
+const MAX_VOLUME: f64 = 1000.0;
+
+// Whole volume numbers between the first and the last that aren't in the
+// results, as the first and last of each run of them
+pub fn gaps(volumes: &[Volume]) -> Vec<(i64, i64)> {
+    let mut numbers: Vec<i64> = volumes
+        .iter()
+        .filter_map(|volume| volume.number)
+        .filter(|number| number.fract() == 0.0 && (0.0..=MAX_VOLUME).contains(number))
+        .map(|number| number as i64)
+        .collect();
+    numbers.sort_unstable();
+    numbers.dedup();
+    numbers
+        .windows(2)
+        .filter(|pair| pair[1] > pair[0] + 1)
+        .map(|pair| (pair[0] + 1, pair[1] - 1))
+        .collect()
+}
+
+// series names first, then volume numbers, ignoring words like "book"
+pub fn compare(a: &str, b: &str) -> Ordering {
+    let (a_name, a_number) = parse_series(a);
+    let (b_name, b_number) = parse_series(b);
+    a_name
+        .to_lowercase()
+        .cmp(&b_name.to_lowercase())
+        .then_with(|| match (a_number, b_number) {
+            (Some(a), Some(b)) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+            (a, b) => a.is_some().cmp(&b.is_some()),
+        })
+}
+
+pub fn render(
+    ui: &mut egui::Ui,
+    view: &mut SeriesView,
+    books: &[BookRef],
+    download: &download::Download,
+) {
+    let series = view.grouped.get_or_insert_with(|| group(books));
+    if series.is_empty() {
+        ui.label("None of the results are in a series");
+        return;
+    }
+    egui::ScrollArea::vertical().show(ui, |ui| {
+        for series in series.iter() {
+            render_series(ui, series, download);
+        }
+    });
+}
+
+fn render_series(ui: &mut egui::Ui, series: &Series, download: &download::Download) {
+    let states: Vec<(&str, &BookRef)> = series.volumes.iter().map(Volume::state).collect();
+    let have = states
+        .iter()
+        .filter(|(status, _)| *status == "have")
+        .count();
+    let title = format!(
+        "{} ({} of {} volumes downloaded)",
+        series.name,
+        have,
+        series.volumes.len()
+    );
+    egui::CollapsingHeader::new(title)
+        .id_source(("series", series.name.to_lowercase()))
+        .show(ui, |ui| {
+            let missing: Vec<&BookRef> = states
+                .iter()
+                .filter(|(status, _)| *status == "missing")
+                .map(|(_, edition)| *edition)
+                .collect();
+            if !missing.is_empty()
+                && ui
+                    .button(format!("Queue {} missing volumes", missing.len()))
+                    .clicked()
+            {
+                // in volume order, so the first ones arrive first
+                for edition in missing {
+                    queue_download(download, edition);
+                }
+            }
+            if !series.gaps.is_empty() {
+                let gaps: Vec<String> = series
+                    .gaps
+                    .iter()
+                    .map(|(first, last)| match first == last {
+                        true => first.to_string(),
+                        false => format!("{first}–{last}"),
+                    })
+                    .collect();
+                ui.label(format!("Not in these results: {}", gaps.join(", ")));
+            }
+            egui::Grid::new(("series_volumes", series.name.to_lowercase()))
+                .striped(true)
+                .show(ui, |ui| {
+                    for (volume, (status, edition)) in series.volumes.iter().zip(&states) {
+                        match volume.number {
+                            Some(number) => ui.label(number.to_string()),
+                            None => ui.label(""),
+                        };
+                        ui.label(*status);
+                        download_button(ui, download, edition);
+                        ui.label(edition.title.as_str());
+                        ui.label(edition.authors.as_str());
+                        match volume.editions.len() {
+                            1 => ui.label(""),
+                            n => ui.label(format!("{n} editions")),
+                        };
+                        ui.end_row();
+                    }
+                });
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::db::Book;
+
+    fn book(title: &str, series: &str) -> BookRef {
+        Arc::new(Book {
+            title: title.to_string(),
+            series: series.to_string(),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_group() {
+        let books = vec![
+            book("Eric", "Discworld 9"),
+            book("Nation", ""),
+            book("The Colour of Magic", "Discworld, Book 1"),
+            book("Eric", "discworld 9"),
+            book("Mort", "Discworld #4"),
+            book("The Science of Discworld", "Discworld"),
+            book("Coraline", "Coraline"),
+        ];
+        let series = group(&books);
+        let names: Vec<&str> = series.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec!["Coraline", "Discworld"]);
+        let volumes: Vec<(Option<f64>, &str, usize)> = series[1]
+            .volumes
+            .iter()
+            .map(|v| (v.number, v.editions[0].title.as_str(), v.editions.len()))
+            .collect();
+        assert_eq!(
+            volumes,
+            vec![
+                (Some(1.0), "The Colour of Magic", 1),
+                (Some(4.0), "Mort", 1),
+                (Some(9.0), "Eric", 2),
+                (None, "The Science of Discworld", 1),
+            ]
+        );
+        assert_eq!(series[1].gaps, vec![(2, 3), (5, 8)]);
+        assert!(series[0].gaps.is_empty());
+    }
+
+    #[test]
+    fn test_gaps() {
+        let books = vec![
+            book("Horus Rising", "Warhammer 40000"),
+            book("Space Wolf", "Warhammer 1"),
+            book("Ragnar's Claw", "Warhammer 3"),
+            book("Algorithms", "Lecture Notes in Computer Science 4231"),
+            book("Sorting", "Lecture Notes in Computer Science 9780306406157"),
+        ];
+        let series = group(&books);
+        assert_eq!(series[0].name, "Lecture Notes in Computer Science");
+        assert!(series[0].gaps.is_empty());
+        // only the numbers that could be volumes
+        assert_eq!(series[1].gaps, vec![(2, 2)]);
+    }
+
+    #[test]
+    fn test_compare() {
+        let mut series = vec!["Discworld 10", "Alpha", "Discworld, Book 9", "Discworld"];
+        series.sort_by(|a, b| compare(a, b));
+        assert_eq!(
+            series,
+            vec!["Alpha", "Discworld", "Discworld, Book 9", "Discworld 10"]
+        );
+    }
+}
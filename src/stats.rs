@@ -0,0 +1,62 @@
+use crate::db::Stats;
+
+// bars in each chart
+const SHOWN: usize = 15;
+
+pub fn render(ui: &mut egui::Ui, stats: &Stats) {
+    let books: i64 = stats.group("Collection").iter().map(|(_, n)| n).sum();
+    let bytes: i64 = stats.group("Size").iter().map(|(_, n)| n).sum();
+    ui.label(format!("{} books, {}", books, format_bytes(bytes)));
+    egui::ScrollArea::vertical().show(ui, |ui| {
+        for (group, open) in [
+            ("Collection", true),
+            ("Size", false),
+            ("Language", true),
+            ("Format", true),
+            ("Decade", false),
+            ("Author", false),
+            ("Publisher", false),
+        ] {
+            let mut values = stats.group(group);
+            let format: fn(i64) -> String = match group {
+                "Size" => format_bytes,
+                _ => |n| n.to_string(),
+            };
+            if group == "Decade" {
+                // oldest first, and all of them
+                values.sort_by(|a, b| a.0.cmp(b.0));
+            } else {
+                values.truncate(SHOWN);
+            }
+            egui::CollapsingHeader::new(group)
+                .default_open(open)
+                .show(ui, |ui| render_bars(ui, group, &values, format));
+        }
+    });
+}
+
+fn render_bars(ui: &mut egui::Ui, group: &str, values: &[(&str, i64)], format: fn(i64) -> String) {
+    let max = values.iter().map(|(_, n)| *n).max().unwrap_or(1).max(1);
+    egui::Grid::new(group).striped(true).show(ui, |ui| {
+        for (value, n) in values {
+            ui.label(*value);
+            ui.add(
+                egui::ProgressBar::new(*n as f32 / max as f32)
+                    .desired_width(250.0)
+                    .text(format(*n)),
+            );
+            ui.end_row();
+        }
+    });
+}
+
+pub fn format_bytes(bytes: i64) -> String {
+    let units = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, units[unit])
+}
@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+
+use egui::collapsing_header::CollapsingState;
+
+use crate::db::Topic;
+
+// The non-fiction topics arranged under their parents
+#[derive(Debug, Default)]
+pub struct TopicTree {
+    topics: Vec<Topic>,
+    // indexes into `topics`
+    children: HashMap<i64, Vec<usize>>,
+    roots: Vec<usize>,
+    // books in each topic including its subtopics, by index
+    totals: Vec<usize>,
+}
+
+impl TopicTree {
+    pub fn new(topics: Vec<Topic>) -> Self {
+        let index: HashMap<i64, usize> =
+            topics.iter().enumerate().map(|(i, t)| (t.id, i)).collect();
+        let mut children: HashMap<i64, Vec<usize>> = HashMap::new();
+        let mut roots = Vec::new();
+        for (i, topic) in topics.iter().enumerate() {
+            // a topic that is its own parent, or whose parent is missing, is at the top
+            if topic.parent != topic.id && index.contains_key(&topic.parent) {
+                children.entry(topic.parent).or_default().push(i);
+            } else {
+                roots.push(i);
+            }
+        }
+        // add each topic's own books to it and to everything above it
+        let mut totals = vec![0; topics.len()];
+        for (i, topic) in topics.iter().enumerate() {
+            let mut at = i;
+            // stops on a loop in the parent links as well as at the top
+            for _ in 0..topics.len() {
+                totals[at] += topic.count;
+                match index.get(&topics[at].parent) {
+                    Some(&parent) if parent != at => at = parent,
+                    _ => break,
+                }
+            }
+        }
+        Self {
+            topics,
+            children,
+            roots,
+            totals,
+        }
+    }
+
+    // the topic and everything under it, the topic itself first
+    pub fn subtree(&self, id: i64) -> Vec<i64> {
+        let mut ids = vec![id];
+        let mut i = 0;
+        while i < ids.len() {
+            for &child in self.children.get(&ids[i]).into_iter().flatten() {
+                let child = self.topics[child].id;
+                if !ids.contains(&child) {
+                    ids.push(child);
+                }
+            }
+            i += 1;
+        }
+        ids
+    }
+
+    pub fn name(&self, id: i64) -> Option<&str> {
+        self.topics
+            .iter()
+            .find(|t| t.id == id)
+            .map(|t| short_name(&t.name))
+    }
+}
+
+// "Technology\Aerospace Equipment" is shown as "Aerospace Equipment"
+fn short_name(name: &str) -> &str {
+    name.rsplit('\\').next().unwrap_or(name).trim()
+}
+
+// Returns true when a topic is picked or unpicked. `selected` holds the ids to
+// filter on, the picked topic first.
+pub fn render(ui: &mut egui::Ui, tree: &TopicTree, selected: &mut Vec<i64>) -> bool {
+    let mut changed = false;
+    for &i in &tree.roots {
+        changed |= render_node(ui, tree, i, selected);
+    }
+    changed
+}
+
+fn render_node(ui: &mut egui::Ui, tree: &TopicTree, i: usize, selected: &mut Vec<i64>) -> bool {
+    // topics with no books only get in the way
+    if tree.totals[i] == 0 {
+        return false;
+    }
+    let topic = &tree.topics[i];
+    let is_selected = selected.first() == Some(&topic.id);
+    let label = format!("{} ({})", short_name(&topic.name), tree.totals[i]);
+    let mut clicked = false;
+    let mut changed = false;
+    match tree.children.get(&topic.id) {
+        Some(children) => {
+            let id = ui.make_persistent_id(("topic", topic.id));
+            CollapsingState::load_with_default_open(ui.ctx(), id, false)
+                .show_header(ui, |ui| {
+                    clicked = ui.selectable_label(is_selected, label).clicked();
+                })
+                .body(|ui| {
+                    for &child in children {
+                        changed |= render_node(ui, tree, child, selected);
+                    }
+                });
+        }
+        None => clicked = ui.selectable_label(is_selected, label).clicked(),
+    }
+    if clicked {
+        // clicking the chosen topic again clears the filter
+        *selected = match is_selected {
+            true => vec![],
+            false => tree.subtree(topic.id),
+        };
+        changed = true;
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn topic(id: i64, parent: i64, name: &str, count: usize) -> Topic {
+        Topic {
+            id,
+            parent,
+            name: name.to_string(),
+            count,
+        }
+    }
+
+    #[test]
+    fn test_tree() {
+        let tree = TopicTree::new(vec![
+            topic(210, 210, "Technology", 1),
+            topic(211, 210, "Technology\\Aerospace", 2),
+            topic(212, 211, "Technology\\Aerospace\\Jets", 4),
+            topic(57, 0, "Art", 8),
+        ]);
+        assert_eq!(tree.roots, vec![0, 3]);
+        assert_eq!(tree.totals, vec![7, 6, 4, 8]);
+        assert_eq!(tree.subtree(210), vec![210, 211, 212]);
+        assert_eq!(tree.subtree(212), vec![212]);
+        assert_eq!(tree.name(211), Some("Aerospace"));
+
+        // a loop in the parent links doesn't hang
+        let tree = TopicTree::new(vec![topic(1, 2, "A", 1), topic(2, 1, "B", 1)]);
+        assert_eq!(tree.subtree(1), vec![1, 2]);
+    }
+}
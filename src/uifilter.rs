@@ -3,7 +3,7 @@ use std::collections::{
     BTreeMap,
 };
 
-use crate::db::BookRef;
+use crate::db::{parse_year, BookRef};
 
 // Whittle down the list of books by choosing, for each combination of title and
 // author, the one with the most recent year.
@@ -30,9 +30,7 @@ fn clean_title(title: &str) -> String {
         .to_lowercase()
         .replace("the ", "")
         .replace("a ", "")
-        .replace('\'', "")
-        .replace('-', "")
-        .replace(' ', "")
+        .replace(['\'', '-', ' '], "")
         // many titles have junk in braces at the end
         .split(|c: char| c.is_ascii_punctuation())
         .next()
@@ -116,8 +114,8 @@ fn compare(old: &BookRef, new: &BookRef) -> bool {
         return old.publisher.trim().is_empty();
     }
 
-    let oldyear = old.year.parse::<u32>().unwrap_or(0);
-    let newyear = new.year.parse::<u32>().unwrap_or(0);
+    let oldyear = parse_year(&old.year).unwrap_or(0);
+    let newyear = parse_year(&new.year).unwrap_or(0);
     if oldyear == newyear {
         // if the years are the same, prefer the one with the most authors
         old.authors.split(',').count() < new.authors.split(',').count()
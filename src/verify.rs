@@ -0,0 +1,321 @@
+use std::{
+    fs::File,
+    io::{self, Read},
+    path::Path,
+};
+
+use blake2::{digest::consts::U32, Blake2b};
+use md5::Md5;
+use sha2::{Digest, Sha256};
+
+// what `ipfs add` cuts files into, and how many of those one node links to
+const CHUNK: usize = 256 * 1024;
+const LINKS: usize = 174;
+
+// multicodecs and multihashes, https://github.com/multiformats/multicodec
+const DAG_PB: u64 = 0x70;
+const RAW: u64 = 0x55;
+const SHA2_256: u64 = 0x12;
+const BLAKE2B_256: u64 = 0xb220;
+
+// The md5 of a file, in lower case hex like the catalog's
+pub fn md5_file(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut md5 = Md5::new();
+    let mut buffer = vec![0; 1024 * 1024];
+    loop {
+        match file.read(&mut buffer)? {
+            0 => break,
+            n => md5.update(&buffer[..n]),
+        }
+    }
+    Ok(md5.finalize().iter().map(|b| format!("{b:02x}")).collect())
+}
+
+// What IPFS would call a file, worked out the way `ipfs add` does it by
+// default for the kind of CID `like` is: 256 KiB chunks in a balanced tree,
+// with raw leaves for version 1 CIDs. A book added some other way gets a
+// different CID even though it's the same file.
+pub fn file_cid(path: &Path, like: &str) -> Result<String, String> {
+    let (version, hash) = match like.chars().next() {
+        Some('Q') => (0, SHA2_256),
+        Some('b') => {
+            let bytes = base32_decode(&like[1..]).ok_or("Not a base32 CID")?;
+            let mut rest = bytes.as_slice();
+            let version = read_varint(&mut rest).ok_or("Bad CID")?;
+            let _codec = read_varint(&mut rest).ok_or("Bad CID")?;
+            let hash = read_varint(&mut rest).ok_or("Bad CID")?;
+            (version, hash)
+        }
+        _ => return Err(format!("Can't read CID {like}")),
+    };
+    if hash != SHA2_256 && hash != BLAKE2B_256 {
+        return Err(format!("Unsupported hash {hash:#x} in {like}"));
+    }
+    let builder = Builder { version, hash };
+    let mut file = File::open(path).map_err(|e| e.to_string())?;
+    let root = builder.build(&mut file).map_err(|e| e.to_string())?;
+    Ok(builder.cid_string(&root.cid))
+}
+
+// a block in the tree: its CID, the bytes of the file under it, and the size
+// of all the blocks under it
+struct Node {
+    cid: Vec<u8>,
+    size: u64,
+    tsize: u64,
+}
+
+struct Builder {
+    version: u64,
+    hash: u64,
+}
+
+impl Builder {
+    fn build(&self, file: &mut File) -> io::Result<Node> {
+        let mut nodes = Vec::new();
+        let mut chunk = vec![0; CHUNK];
+        loop {
+            let n = read_full(file, &mut chunk)?;
+            if n == 0 && !nodes.is_empty() {
+                break;
+            }
+            nodes.push(self.leaf(&chunk[..n]));
+            if n < CHUNK {
+                break;
+            }
+        }
+        // each level links to up to LINKS of the one below, left to right
+        while nodes.len() > 1 {
+            nodes = nodes
+                .chunks(LINKS)
+                .map(|links| self.parent(links))
+                .collect();
+        }
+        Ok(nodes.remove(0))
+    }
+
+    fn leaf(&self, data: &[u8]) -> Node {
+        if self.version == 1 {
+            return Node {
+                cid: self.cid(RAW, data),
+                size: data.len() as u64,
+                tsize: data.len() as u64,
+            };
+        }
+        let mut unixfs = Vec::new();
+        field_varint(&mut unixfs, 1, 2); // a file
+        if !data.is_empty() {
+            field_bytes(&mut unixfs, 2, data);
+        }
+        field_varint(&mut unixfs, 3, data.len() as u64);
+        let mut block = Vec::new();
+        field_bytes(&mut block, 1, &unixfs);
+        Node {
+            cid: self.cid(DAG_PB, &block),
+            size: data.len() as u64,
+            tsize: block.len() as u64,
+        }
+    }
+
+    fn parent(&self, links: &[Node]) -> Node {
+        let size: u64 = links.iter().map(|link| link.size).sum();
+        let mut unixfs = Vec::new();
+        field_varint(&mut unixfs, 1, 2);
+        field_varint(&mut unixfs, 3, size);
+        for link in links {
+            field_varint(&mut unixfs, 4, link.size);
+        }
+        // links come before the data, as go-ipfs writes them
+        let mut block = Vec::new();
+        for link in links {
+            let mut encoded = Vec::new();
+            field_bytes(&mut encoded, 1, &link.cid);
+            field_bytes(&mut encoded, 2, b"");
+            field_varint(&mut encoded, 3, link.tsize);
+            field_bytes(&mut block, 2, &encoded);
+        }
+        field_bytes(&mut block, 1, &unixfs);
+        Node {
+            cid: self.cid(DAG_PB, &block),
+            size,
+            tsize: block.len() as u64 + links.iter().map(|link| link.tsize).sum::<u64>(),
+        }
+    }
+
+    // the binary CID of a block
+    fn cid(&self, codec: u64, block: &[u8]) -> Vec<u8> {
+        let digest: Vec<u8> = match self.hash {
+            BLAKE2B_256 => Blake2b::<U32>::digest(block).to_vec(),
+            _ => Sha256::digest(block).to_vec(),
+        };
+        let mut cid = Vec::new();
+        if self.version == 1 {
+            write_varint(&mut cid, 1);
+            write_varint(&mut cid, codec);
+        }
+        write_varint(&mut cid, self.hash);
+        write_varint(&mut cid, digest.len() as u64);
+        cid.extend(digest);
+        cid
+    }
+
+    fn cid_string(&self, cid: &[u8]) -> String {
+        match self.version {
+            0 => base58_encode(cid),
+            _ => format!("b{}", base32_encode(cid)),
+        }
+    }
+}
+
+// fill `buffer` unless the file ends first
+fn read_full(file: &mut File, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match file.read(&mut buffer[filled..])? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    Ok(filled)
+}
+
+// protobuf fields, https://protobuf.dev/programming-guides/encoding/
+fn field_varint(out: &mut Vec<u8>, field: u64, value: u64) {
+    write_varint(out, field << 3);
+    write_varint(out, value);
+}
+
+fn field_bytes(out: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    write_varint(out, field << 3 | 2);
+    write_varint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(bytes: &mut &[u8]) -> Option<u64> {
+    let mut value = 0;
+    for (i, byte) in bytes.iter().enumerate().take(9) {
+        value |= u64::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            *bytes = &bytes[i + 1..];
+            return Some(value);
+        }
+    }
+    None
+}
+
+const BASE32: &[u8] = b"abcdefghijklmnopqrstuvwxyz234567";
+const BASE58: &[u8] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+// lower case and unpadded, as CIDs are written
+fn base32_encode(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    let (mut buffer, mut bits) = (0u32, 0);
+    for byte in bytes {
+        buffer = buffer << 8 | u32::from(*byte);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32[(buffer >> bits) as usize & 31] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(BASE32[(buffer << (5 - bits)) as usize & 31] as char);
+    }
+    out
+}
+
+fn base32_decode(text: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    let (mut buffer, mut bits) = (0u32, 0);
+    for c in text.bytes() {
+        let value = BASE32.iter().position(|b| *b == c.to_ascii_lowercase())?;
+        buffer = buffer << 5 | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+fn base58_encode(bytes: &[u8]) -> String {
+    // base 58 digits, least significant first
+    let mut digits: Vec<u8> = Vec::new();
+    for byte in bytes {
+        let mut carry = u32::from(*byte);
+        for digit in digits.iter_mut() {
+            carry += u32::from(*digit) << 8;
+            *digit = (carry % 58) as u8;
+            carry /= 58;
+        }
+        while carry > 0 {
+            digits.push((carry % 58) as u8);
+            carry /= 58;
+        }
+    }
+    let zeros = bytes.iter().take_while(|b| **b == 0).count();
+    std::iter::repeat('1')
+        .take(zeros)
+        .chain(digits.iter().rev().map(|d| BASE58[*d as usize] as char))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cid_of(contents: &[u8], like: &str) -> String {
+        let path = std::env::temp_dir().join(format!("rlgdesktop-test-cid-{}", contents.len()));
+        std::fs::write(&path, contents).unwrap();
+        let cid = file_cid(&path, like).unwrap();
+        let _ = std::fs::remove_file(&path);
+        cid
+    }
+
+    #[test]
+    fn test_md5() {
+        let path = std::env::temp_dir().join("rlgdesktop-test-md5");
+        std::fs::write(&path, b"hello world\n").unwrap();
+        assert_eq!(md5_file(&path).unwrap(), "6f5902ac237024bdd0c176cb93063dc4");
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_cid() {
+        let v0 = "QmT78zSuBmuS4z925WZfrqQ1qHaJ56DQaTfyMUF7F8ff5o";
+        let v1 = "bafkreifjjcie6lypi6ny7amxnfftagclbuxndqonfipmb64f2km2devei4";
+        // what `ipfs add` says for "hello world\n"
+        assert_eq!(cid_of(b"hello world\n", v0), v0);
+        assert_eq!(cid_of(b"hello world\n", v1), v1);
+        assert_eq!(
+            cid_of(b"", v0),
+            "QmbFMke1KXqnYyBBWxB74N4c5SBnJMVAiMNRcGu6x1AwQH"
+        );
+        // several chunks get a tree, whose root is a dag-pb node
+        let big = vec![7; CHUNK * 3 + 1];
+        assert!(cid_of(&big, v1).starts_with("bafybei"));
+        assert!(cid_of(&big, "bafykbzacea").starts_with("bafykbza"));
+        assert!(file_cid(Path::new("missing"), "zdj7W").is_err());
+    }
+
+    #[test]
+    fn test_encodings() {
+        let mut varint = Vec::new();
+        write_varint(&mut varint, BLAKE2B_256);
+        assert_eq!(varint, vec![0xa0, 0xe4, 0x02]);
+        assert_eq!(read_varint(&mut varint.as_slice()), Some(BLAKE2B_256));
+        let bytes = b"any bytes at all";
+        assert_eq!(base32_decode(&base32_encode(bytes)).unwrap(), bytes);
+        assert_eq!(base58_encode(&[0, 0, 1]), "112");
+    }
+}